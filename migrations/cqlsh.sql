@@ -10,7 +10,7 @@ USE volksforo;
 --
 DROP TABLE IF EXISTS attachments;
 CREATE TABLE attachments (
-    hash text PRIMARY KEY, -- scylla devs text primary keys are AOK because of their hashing algo
+    hash text, -- scylla devs text primary keys are AOK because of their hashing algo
     first_seen_at timestamp,
     last_seen_at timestamp,
     filesize bigint,
@@ -18,31 +18,55 @@ CREATE TABLE attachments (
     PRIMARY KEY (hash)
 );
 
+-- Uploads are stripped of metadata before they are hashed and stored, so clients hashing the file
+-- they have cannot find it by that hash. This maps the hash of each file as uploaded to the stored one.
+DROP TABLE IF EXISTS attachment_sources;
+CREATE TABLE attachment_sources (
+    source_hash text PRIMARY KEY,
+    attachment_hash text
+);
+
 DROP TABLE IF EXISTS attachment_views;
 CREATE TABLE attachment_views (
     hash text PRIMARY KEY,
     view_count counter
 );
 
+-- How many posts each attachment is linked to. Recounted from post_attachments by the reconciler.
+DROP TABLE IF EXISTS attachment_references;
+CREATE TABLE attachment_references (
+    hash text PRIMARY KEY,
+    reference_count counter
+);
+
 DROP TABLE IF EXISTS attachment_thumbnails;
 CREATE TABLE attachment_thumbnails (
-    attachment_hash text PRIMARY KEY,
+    attachment_hash text,
+    size int, -- bounding box the thumbnail was scaled into
     thumbnail_hash text,
-    dimension: tuple<int, int>
+    dimension tuple<int, int>,
+    PRIMARY KEY (attachment_hash, size)
 );
 
 DROP TABLE IF EXISTS post_attachments;
 CREATE TABLE post_attachments (
-    post_id bigint PRIMARY KEY,
+    post_id bigint,
     attachment_hash text,
+    filename text,
+    PRIMARY KEY (post_id, attachment_hash)
 );
 
+-- Counts the posts a file is attached to when reconciling reference counts.
+DROP INDEX IF EXISTS post_attachments_by_hash;
+CREATE INDEX post_attachments_by_hash ON volksforo.post_attachments (attachment_hash);
+
 --
 -- Nodes
 --
 DROP TABLE IF EXISTS nodes;
 CREATE TABLE nodes (
     id bigint,
+    parent_id bigint, -- permissions are inherited from the parent node
     display_order int,
     title text,
     description text,
@@ -67,6 +91,9 @@ CREATE TABLE threads (
     first_post_user_id bigint,
     last_post_id bigint,
     last_post_user_id bigint,
+    locked boolean,
+    sticky boolean,
+    redirect_thread_id bigint, -- set on stubs left behind by moves and merges
     PRIMARY KEY (node_id, bucket_id, last_post_id)
 ) WITH CLUSTERING ORDER BY (bucket_id ASC, last_post_id ASC);
 
@@ -76,9 +103,17 @@ CREATE TABLE threads (
 DROP INDEX IF EXISTS threads_by_id;
 CREATE INDEX threads_by_id ON volksforo.threads (id);
 
-INSERT INTO threads (id, node_id, bucket_id, title, created_at, first_post_id, first_post_user_id, last_post_id, last_post_user_id) VALUES (1, 1, 1, 'Test Thread', '2023-03-12T14:27:00+00:00', 1, 1, 7, 1);
-INSERT INTO threads (id, node_id, bucket_id, title, created_at, first_post_id, first_post_user_id, last_post_id, last_post_user_id) VALUES (2, 1, 1, 'Other Thread', '2023-03-12T14:27:03+00:00', 4, 420, 4, 420);
-INSERT INTO threads (id, node_id, bucket_id, title, created_at, first_post_id, first_post_user_id, last_post_id, last_post_user_id) VALUES (3, 2, 1, 'Chuck Thread', '2023-03-12T14:27:04+00:00', 5, 69, 5, 69);
+INSERT INTO threads (id, node_id, bucket_id, title, created_at, first_post_id, first_post_user_id, last_post_id, last_post_user_id, locked, sticky) VALUES (1, 1, 1, 'Test Thread', '2023-03-12T14:27:00+00:00', 1, 1, 7, 1, false, false);
+INSERT INTO threads (id, node_id, bucket_id, title, created_at, first_post_id, first_post_user_id, last_post_id, last_post_user_id, locked, sticky) VALUES (2, 1, 1, 'Other Thread', '2023-03-12T14:27:03+00:00', 4, 420, 4, 420, false, false);
+INSERT INTO threads (id, node_id, bucket_id, title, created_at, first_post_id, first_post_user_id, last_post_id, last_post_user_id, locked, sticky) VALUES (3, 2, 1, 'Chuck Thread', '2023-03-12T14:27:04+00:00', 5, 69, 5, 69, false, false);
+
+-- Sticky threads per forum, listed above the rest.
+DROP TABLE IF EXISTS sticky_threads;
+CREATE TABLE sticky_threads (
+    node_id bigint,
+    thread_id bigint,
+    PRIMARY KEY (node_id, thread_id)
+);
 
 -- Thread view counter table
 -- These are special in Scylla.
@@ -96,10 +131,20 @@ CREATE TABLE thread_replies (
 );
 
 -- No inserts, only updates.
-UPDATE thread_replies SET reply_count = reply_count + 4 WHERE id = 1;
+UPDATE thread_replies SET reply_count = reply_count + 5 WHERE id = 1;
 UPDATE thread_replies SET reply_count = reply_count + 1 WHERE id = 2;
 UPDATE thread_replies SET reply_count = reply_count + 1 WHERE id = 3;
 
+-- Thread counts per forum, for paginating forum listings.
+DROP TABLE IF EXISTS node_threads;
+CREATE TABLE node_threads (
+    id bigint PRIMARY KEY,
+    thread_count counter
+);
+
+UPDATE node_threads SET thread_count = thread_count + 2 WHERE id = 1;
+UPDATE node_threads SET thread_count = thread_count + 1 WHERE id = 2;
+
 --
 -- Posts
 --
@@ -113,6 +158,10 @@ CREATE TABLE posts (
     PRIMARY KEY (id)
 ); -- threads order newest to oldest
 
+-- Lets a user's posts be found for their data export.
+DROP INDEX IF EXISTS posts_by_user_id;
+CREATE INDEX posts_by_user_id ON volksforo.posts (user_id);
+
 
 INSERT INTO posts (id, thread_id, user_id, created_at, ugc_id) VALUES (1, 1, 1, '2023-03-12T14:27:00+00:00', 9d1fe4ff-00a4-418f-8234-8ee2208f85eb);
 INSERT INTO posts (id, thread_id, user_id, created_at, ugc_id) VALUES (2, 1, 69, '2023-03-12T14:27:01+00:00', 077d372c-8836-44e4-a75d-7f119a5ac195);
@@ -133,13 +182,50 @@ CREATE TABLE post_positions (
     PRIMARY KEY (thread_id, position, post_id)
 ) WITH CLUSTERING ORDER BY (position ASC, post_id ASC);
 
+-- Finds a post's position for permalinks. Local to the thread partition.
+DROP INDEX IF EXISTS post_positions_by_post;
+CREATE INDEX post_positions_by_post ON volksforo.post_positions ((thread_id), post_id);
+
 INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 1, 1);
 INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 2, 2);
 INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 3, 3);
 INSERT INTO post_positions (thread_id, position, post_id) VALUES (2, 1, 4);
 INSERT INTO post_positions (thread_id, position, post_id) VALUES (3, 1, 5);
-INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 4, 6); -- duplicate position
-INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 4, 7);
+INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 4, 6);
+INSERT INTO post_positions (thread_id, position, post_id) VALUES (1, 5, 7);
+
+-- Last position handed out per thread. Only ever changed with compare-and-set so replies never share a position.
+DROP TABLE IF EXISTS thread_positions;
+CREATE TABLE thread_positions (
+    thread_id bigint PRIMARY KEY,
+    last_position bigint
+);
+
+INSERT INTO thread_positions (thread_id, last_position) VALUES (1, 5) IF NOT EXISTS;
+INSERT INTO thread_positions (thread_id, last_position) VALUES (2, 1) IF NOT EXISTS;
+INSERT INTO thread_positions (thread_id, last_position) VALUES (3, 1) IF NOT EXISTS;
+
+--
+-- Deletions
+--
+-- Soft-deleted posts and threads have a row here until restored. Purged content keeps its row.
+DROP TABLE IF EXISTS post_deletions;
+CREATE TABLE post_deletions (
+    id bigint,
+    deleted_by bigint,
+    deleted_at timestamp,
+    reason text,
+    PRIMARY KEY (id)
+);
+
+DROP TABLE IF EXISTS thread_deletions;
+CREATE TABLE thread_deletions (
+    id bigint,
+    deleted_by bigint,
+    deleted_at timestamp,
+    reason text,
+    PRIMARY KEY (id)
+);
 
 --
 -- UGC
@@ -154,14 +240,18 @@ CREATE TABLE ugc (
     PRIMARY KEY (id, created_at)
 ) WITH CLUSTERING ORDER BY (created_at DESC);
 
-INSERT INTO ugc (id, created_at, content) VALUES (9d1fe4ff-00a4-418f-8234-8ee2208f85eb, 1, '2023-03-12T14:27:00+00:00', 'First post');
-INSERT INTO ugc (id, created_at, content) VALUES (077d372c-8836-44e4-a75d-7f119a5ac195, 69, '2023-03-12T14:27:01+00:00', 'Second post');
-INSERT INTO ugc (id, created_at, content) VALUES (90d07d83-1736-491d-872f-9fce4d5250a9, 420, '2023-03-12T14:27:02+00:00', 'Third post');
-INSERT INTO ugc (id, created_at, content) VALUES (8fabdde1-1ccb-42ab-8cd3-70c91fe571c6, 420, '2023-03-12T14:27:03+00:00', 'Fourth post');
-INSERT INTO ugc (id, created_at, content) VALUES (0c287743-199f-4160-95b0-4992785b62a2, 69, '2023-03-12T14:27:04+00:00', 'Fifth post');
-INSERT INTO ugc (id, created_at, content) VALUES (0ec2e499-356f-465a-bb37-ad9904f29122, 69, '2023-03-12T14:27:05+00:00', 'Sixth post');
-INSERT INTO ugc (id, created_at, content) VALUES (cfc00480-3ae0-4af4-ab5e-542414c9c968, 1, '2023-03-12T14:27:06+00:00', 'Sixth post');
-INSERT INTO ugc (id, created_at, content) VALUES (cfc00480-3ae0-4af4-ab5e-542414c9c968, 1, '2023-03-12T14:27:07+00:00', 'Seventh* post, sorry'); -- edited post
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (9d1fe4ff-00a4-418f-8234-8ee2208f85eb, 1, '2023-03-12T14:27:00+00:00', 'First post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (077d372c-8836-44e4-a75d-7f119a5ac195, 69, '2023-03-12T14:27:01+00:00', 'Second post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (90d07d83-1736-491d-872f-9fce4d5250a9, 420, '2023-03-12T14:27:02+00:00', 'Third post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (8fabdde1-1ccb-42ab-8cd3-70c91fe571c6, 420, '2023-03-12T14:27:03+00:00', 'Fourth post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (0c287743-199f-4160-95b0-4992785b62a2, 69, '2023-03-12T14:27:04+00:00', 'Fifth post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (0ec2e499-356f-465a-bb37-ad9904f29122, 69, '2023-03-12T14:27:05+00:00', 'Sixth post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (cfc00480-3ae0-4af4-ab5e-542414c9c968, 1, '2023-03-12T14:27:06+00:00', 'Sixth post');
+INSERT INTO ugc (id, user_id, created_at, content) VALUES (cfc00480-3ae0-4af4-ab5e-542414c9c968, 1, '2023-03-12T14:27:07+00:00', 'Seventh* post, sorry'); -- edited post
+
+-- Lets a user's revisions be found when their account is deleted.
+DROP INDEX IF EXISTS ugc_by_user_id;
+CREATE INDEX ugc_by_user_id ON volksforo.ugc (user_id);
 
 --
 -- User
@@ -177,13 +267,96 @@ CREATE TABLE users (
     PRIMARY KEY (id)
 );
 
+-- Replaced by usernames_by_normal, which can be claimed with a lightweight transaction.
 DROP INDEX IF EXISTS users_by_name_normal;
-CREATE INDEX users_by_name_normal ON volksforo.users (username_normal);
 
-INSERT INTO users (id, username, username_normal, password, password_cipher) VALUES (1, 'admin', 'admin', 'password', 'plaintext');
+INSERT INTO users (id, username, username_normal, password, password_cipher) VALUES (1, 'admin', 'adrnin', 'password', 'plaintext');
 INSERT INTO users (id, username, username_normal, password, password_cipher) VALUES (69, 'Sneed', 'sneed', 'password', 'plaintext');
 INSERT INTO users (id, username, username_normal, password, password_cipher) VALUES (420, 'Chuck', 'chuck', 'password', 'plaintext');
 
+-- Names and emails are claimed here before a user is inserted so no two users share one.
+-- Normal forms come from util::normalize_username and util::normalize_email.
+DROP TABLE IF EXISTS usernames_by_normal;
+CREATE TABLE usernames_by_normal (
+    username_normal text,
+    user_id bigint,
+    PRIMARY KEY (username_normal)
+);
+
+INSERT INTO usernames_by_normal (username_normal, user_id) VALUES ('adrnin', 1);
+INSERT INTO usernames_by_normal (username_normal, user_id) VALUES ('sneed', 69);
+INSERT INTO usernames_by_normal (username_normal, user_id) VALUES ('chuck', 420);
+
+-- Every name a user has gone by. Content refers to names through here so they outlive the user row.
+DROP TABLE IF EXISTS user_name;
+CREATE TABLE user_name (
+    user_id bigint,
+    created_at timestamp,
+    username text,
+    PRIMARY KEY (user_id, created_at)
+) WITH CLUSTERING ORDER BY (created_at DESC);
+
+INSERT INTO user_name (user_id, created_at, username) VALUES (1, '2023-03-12T14:00:00+00:00', 'admin');
+INSERT INTO user_name (user_id, created_at, username) VALUES (69, '2023-03-12T14:00:00+00:00', 'Sneed');
+INSERT INTO user_name (user_id, created_at, username) VALUES (420, '2023-03-12T14:00:00+00:00', 'Chuck');
+
+DROP TABLE IF EXISTS emails_by_normal;
+CREATE TABLE emails_by_normal (
+    email_normal text,
+    user_id bigint,
+    PRIMARY KEY (email_normal)
+);
+
+-- Account deletions. See account_deletion.rs.
+DROP TABLE IF EXISTS user_deletions;
+CREATE TABLE user_deletions (
+    user_id bigint,
+    requested_by bigint,
+    requested_at timestamp,
+    erase_name boolean,
+    finished_at timestamp,
+    PRIMARY KEY (user_id)
+);
+
+-- Personal data archives. Rows and their files are removed once they expire. See export.rs.
+DROP TABLE IF EXISTS user_exports;
+CREATE TABLE user_exports (
+    user_id bigint,
+    id uuid,
+    requested_at timestamp,
+    finished_at timestamp,
+    expires_at timestamp,
+    PRIMARY KEY (user_id, id)
+);
+
+--
+-- User Groups
+--
+DROP TABLE IF EXISTS groups;
+CREATE TABLE groups (
+    id int PRIMARY KEY,
+    label text
+);
+
+-- 1 and 2 are assigned automatically to guests and registered users.
+INSERT INTO groups (id, label) VALUES (1, 'Guests');
+INSERT INTO groups (id, label) VALUES (2, 'Registered');
+INSERT INTO groups (id, label) VALUES (3, 'Moderators');
+INSERT INTO groups (id, label) VALUES (4, 'VIP');
+
+DROP TABLE IF EXISTS user_groups;
+CREATE TABLE user_groups (
+    user_id bigint,
+    group_id int,
+    PRIMARY KEY (user_id, group_id)
+);
+
+INSERT INTO user_groups (user_id, group_id) VALUES (1, 2);
+INSERT INTO user_groups (user_id, group_id) VALUES (1, 3);
+INSERT INTO user_groups (user_id, group_id) VALUES (69, 2);
+INSERT INTO user_groups (user_id, group_id) VALUES (69, 4);
+INSERT INTO user_groups (user_id, group_id) VALUES (420, 2);
+
 --
 -- User Sessions
 --
@@ -193,8 +366,81 @@ CREATE TABLE user_sessions (
     user_id bigint,
     created_at timestamp,
     last_seen_at timestamp,
+    user_agent text,
     PRIMARY KEY(id)
 );
 
 DROP INDEX IF EXISTS user_sessions_by_user_id;
-CREATE INDEX user_sessions_by_user_id ON volksforo.user_sessions (user_id);
\ No newline at end of file
+CREATE INDEX user_sessions_by_user_id ON volksforo.user_sessions (user_id);
+--
+-- Permissions
+--
+DROP TABLE IF EXISTS permission_categories;
+CREATE TABLE permission_categories (
+    id int PRIMARY KEY,
+    label text
+);
+
+INSERT INTO permission_categories (id, label) VALUES (1, 'forum');
+INSERT INTO permission_categories (id, label) VALUES (2, 'moderation');
+
+DROP TABLE IF EXISTS permission_items;
+CREATE TABLE permission_items (
+    id int PRIMARY KEY,
+    category_id int,
+    label text
+);
+
+INSERT INTO permission_items (id, category_id, label) VALUES (1, 1, 'forum.view');
+INSERT INTO permission_items (id, category_id, label) VALUES (2, 1, 'thread.reply');
+INSERT INTO permission_items (id, category_id, label) VALUES (3, 1, 'attachment.upload');
+INSERT INTO permission_items (id, category_id, label) VALUES (4, 1, 'thread.create');
+INSERT INTO permission_items (id, category_id, label) VALUES (5, 1, 'post.edit_own');
+INSERT INTO permission_items (id, category_id, label) VALUES (6, 2, 'post.edit_any');
+INSERT INTO permission_items (id, category_id, label) VALUES (7, 2, 'post.view_history');
+INSERT INTO permission_items (id, category_id, label) VALUES (8, 1, 'post.delete_own');
+INSERT INTO permission_items (id, category_id, label) VALUES (9, 2, 'post.delete_any');
+INSERT INTO permission_items (id, category_id, label) VALUES (10, 2, 'post.view_deleted');
+INSERT INTO permission_items (id, category_id, label) VALUES (11, 2, 'post.hard_delete');
+INSERT INTO permission_items (id, category_id, label) VALUES (12, 2, 'thread.moderate');
+INSERT INTO permission_items (id, category_id, label) VALUES (13, 2, 'user.delete');
+
+-- A collection is a set of values belonging to either a group or a user.
+-- Collections without a node_id are global. Node collections are stacked over their parents.
+DROP TABLE IF EXISTS permission_collections;
+CREATE TABLE permission_collections (
+    id int PRIMARY KEY,
+    group_id int,
+    user_id bigint,
+    node_id bigint
+);
+
+INSERT INTO permission_collections (id, group_id) VALUES (1, 1); -- Guests
+INSERT INTO permission_collections (id, group_id) VALUES (2, 2); -- Registered
+INSERT INTO permission_collections (id, group_id, node_id) VALUES (3, 1, 1); -- Guests in 18+ forum
+INSERT INTO permission_collections (id, group_id) VALUES (4, 3); -- Moderators
+
+-- Values are flags: 1 YES, 0 DEFAULT, -1 NO, -2 NEVER
+DROP TABLE IF EXISTS permission_values;
+CREATE TABLE permission_values (
+    collection_id int,
+    item_id int,
+    value tinyint,
+    PRIMARY KEY (collection_id, item_id)
+);
+
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (1, 1, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (2, 1, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (2, 2, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (2, 3, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (2, 4, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (2, 5, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (2, 8, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (3, 1, -1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 6, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 7, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 9, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 10, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 11, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 12, 1);
+INSERT INTO permission_values (collection_id, item_id, value) VALUES (4, 13, 1);
@@ -1,6 +1,34 @@
 import { blake3 } from 'hash-wasm';
 
 document.addEventListener("DOMContentLoaded", function () {
+    // Requests without the page's CSRF token are refused.
+    let csrfToken = document.querySelector('meta[name="csrf-token"]')?.content ?? '';
+
+    // Adds hidden inputs so the parent form submits the file by its hash.
+    function addAttachmentToForm(inputEl, hash, filename) {
+        let form = inputEl.closest('form');
+        let listEl = form.querySelector('.attachment-list') ?? form;
+
+        let hashEl = document.createElement('input');
+        hashEl.type = 'hidden';
+        hashEl.name = 'attachment_hash';
+        hashEl.value = hash;
+        listEl.appendChild(hashEl);
+
+        let nameEl = document.createElement('input');
+        nameEl.type = 'hidden';
+        nameEl.name = 'attachment_name';
+        nameEl.value = filename;
+        listEl.appendChild(nameEl);
+
+        let labelEl = document.createElement('div');
+        labelEl.textContent = filename;
+        listEl.appendChild(labelEl);
+
+        // The file no longer needs to be sent with the form.
+        inputEl.value = '';
+    }
+
     function attachmentEventListeners(element) {
         let inputEl = document.querySelector('.attachment-input');
         if (inputEl !== null) {
@@ -11,20 +39,25 @@ document.addEventListener("DOMContentLoaded", function () {
 
                     reader.onload = async function (readerEvent) {
                         let hash = await blake3(new Uint8Array(readerEvent.target.result));
-                        let formData = new FormData();
-                        formData.append("hash", hash);
 
                         let response = await fetch('/fs/check-file', {
                             method: "POST",
                             headers: {
-                                'Content-Type': 'application/json'
+                                'Content-Type': 'application/json',
+                                'X-CSRF-Token': csrfToken,
                             },
                             body: JSON.stringify({
                                 hash: hash,
                             }),
                         });
 
-                        console.log(response);
+                        if (response.ok) {
+                            let result = await response.json();
+                            if (result.exists) {
+                                // Submit the file by what it is stored as, not what we hashed.
+                                addAttachmentToForm(inputEl, result.hash, file.name);
+                            }
+                        }
                     }
 
                     reader.onerror = function (readerEvent) {
@@ -41,13 +74,31 @@ document.addEventListener("DOMContentLoaded", function () {
             uploadEl.addEventListener('click', async function (event) {
                 event.preventDefault();
 
+                let inputEl = document.querySelector('.attachment-input');
+                if (inputEl.files.length === 0) {
+                    return false;
+                }
+
                 let formData = new FormData();
-                formData.append("file", document.querySelector('.attachment-input').files[0]);
+                formData.append("attachment", inputEl.files[0]);
 
-                let response = await fetch('/fs/upload-file', {
+                let response = await fetch('/attachments/upload', {
                     method: "POST",
+                    headers: {
+                        'X-CSRF-Token': csrfToken,
+                    },
                     body: formData
                 });
+
+                if (response.ok) {
+                    for (let upload of await response.json()) {
+                        addAttachmentToForm(inputEl, upload.hash, upload.filename);
+                    }
+                }
+                else {
+                    console.log(await response.text());
+                }
+
                 return false; // prevent default
             });
         }
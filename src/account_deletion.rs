@@ -0,0 +1,95 @@
+//! Account deletion.
+//! Deletions run in the background and are recorded in `user_deletions`. Every step can be
+//! repeated, so unfinished deletions are simply run again on startup.
+//! Content is kept and still credited to the user id, under their last name unless it was erased.
+//! UGC revisions are left alone: `ugc.ip_id` is never written, so they hold no address to scrub.
+//! Data exports are removed along with the account.
+
+use crate::filesystem::StorageBackend;
+use crate::model::{Group, User, UserDeletion, UserExport, UserName, UserSession};
+use crate::util::normalize_email;
+use actix_web::web::Data;
+use anyhow::Result;
+use scylla::Session;
+
+/// Starts deleting a user's account. Returns false if it is already being deleted.
+pub async fn request(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    user_id: i64,
+    requested_by: Option<i64>,
+    erase_name: bool,
+) -> Result<bool> {
+    let deletion = UserDeletion::new(user_id, requested_by, erase_name);
+    if !deletion.insert_if_absent(scylla.clone()).await? {
+        return Ok(false);
+    }
+
+    log::info!("Deleting account of user {}.", user_id);
+    spawn(scylla, storage, deletion);
+    Ok(true)
+}
+
+/// Resumes every deletion interrupted by a restart.
+pub async fn resume(scylla: Data<Session>, storage: Data<dyn StorageBackend>) -> Result<()> {
+    for deletion in UserDeletion::fetch_unfinished(scylla.clone()).await? {
+        log::info!("Resuming deletion of user {}.", deletion.user_id);
+        spawn(scylla.clone(), storage.clone(), deletion);
+    }
+
+    Ok(())
+}
+
+/// Runs a deletion in the background.
+fn spawn(scylla: Data<Session>, storage: Data<dyn StorageBackend>, deletion: UserDeletion) {
+    tokio::spawn(async move {
+        let user_id = deletion.user_id;
+        if let Err(err) = run(scylla, storage, deletion).await {
+            log::error!("Deleting user {} failed: {:?}", user_id, err);
+        }
+    });
+}
+
+async fn run(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    mut deletion: UserDeletion,
+) -> Result<()> {
+    erase_account(scylla.clone(), storage, &deletion).await?;
+    deletion.finish(scylla).await?;
+
+    log::info!("Deleted account of user {}.", deletion.user_id);
+    Ok(())
+}
+
+/// Removes the account and everything that identifies the person behind it.
+async fn erase_account(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    deletion: &UserDeletion,
+) -> Result<()> {
+    let user_id = deletion.user_id;
+
+    // The user row goes last. Until then it is how the claims below are found.
+    if let Some(user) = User::fetch(scylla.clone(), user_id).await? {
+        if let Some(email) = &user.email {
+            User::release_email(scylla.clone(), &normalize_email(email), user_id).await?;
+        }
+        // A kept name stays claimed so nobody else can post under it.
+        if deletion.erase_name {
+            User::release_username(scylla.clone(), &user.username_normal, user_id).await?;
+        }
+    }
+
+    UserSession::delete_for_user(scylla.clone(), user_id).await?;
+    // Archives still being built find their request gone and remove themselves.
+    for export in UserExport::fetch_all_for_user(scylla.clone(), user_id).await? {
+        storage.delete(&export.storage_key()).await?;
+        export.delete(scylla.clone()).await?;
+    }
+    Group::remove_user(scylla.clone(), user_id).await?;
+    if deletion.erase_name {
+        UserName::delete_history(scylla.clone(), user_id).await?;
+    }
+    User::delete(scylla, user_id).await
+}
@@ -0,0 +1,143 @@
+//! HTML output for parsed BBCode.
+//! Every element maps to a fixed snippet of markup. Text and attribute values are always escaped.
+
+use super::parse::{Element, Node, Tag};
+
+/// Longest URL we link or embed.
+const MAX_URL_LENGTH: usize = 2048;
+
+/// Escapes text for use in HTML content and quoted attribute values.
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Returns the URL if it is absolute http(s) with nothing which could break out of an attribute.
+/// Other schemes (`javascript:`, `data:`, ...) are never linked.
+pub fn sanitize_url(url: &str) -> Option<&str> {
+    let url = url.trim();
+    let scheme = url.get(..8).unwrap_or(url).to_ascii_lowercase();
+    let rest = if scheme.starts_with("https://") {
+        &url[8..]
+    } else if scheme.starts_with("http://") {
+        &url[7..]
+    } else {
+        return None;
+    };
+
+    if rest.is_empty()
+        || url.len() > MAX_URL_LENGTH
+        || url.chars().any(|c| {
+            c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '<' | '>' | '\\' | '`')
+        })
+    {
+        return None;
+    }
+
+    Some(url)
+}
+
+/// Concatenates the text inside an element, as written.
+fn text_content(element: &Element) -> String {
+    let mut text = String::new();
+    for child in &element.children {
+        match child {
+            Node::Text(t) => text.push_str(t),
+            Node::LineBreak => text.push('\n'),
+            Node::Element(e) => text.push_str(&text_content(e)),
+        }
+    }
+    text
+}
+
+fn write_nodes(out: &mut String, nodes: &[Node]) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => escape_into(out, text),
+            Node::LineBreak => out.push_str("<br />"),
+            Node::Element(element) => write_element(out, element),
+        }
+    }
+}
+
+fn write_wrapped(out: &mut String, open: &str, element: &Element, close: &str) {
+    out.push_str(open);
+    write_nodes(out, &element.children);
+    out.push_str(close);
+}
+
+fn write_element(out: &mut String, element: &Element) {
+    match element.tag {
+        Tag::Bold => write_wrapped(out, "<strong>", element, "</strong>"),
+        Tag::Italic => write_wrapped(out, "<em>", element, "</em>"),
+        Tag::Url => {
+            let href = match &element.argument {
+                Some(argument) => argument.to_owned(),
+                None => text_content(element),
+            };
+            match sanitize_url(&href) {
+                Some(href) => {
+                    out.push_str("<a href=\"");
+                    escape_into(out, href);
+                    out.push_str("\" rel=\"nofollow ugc noopener noreferrer\" target=\"_blank\">");
+                    write_nodes(out, &element.children);
+                    out.push_str("</a>");
+                }
+                None => write_nodes(out, &element.children),
+            }
+        }
+        Tag::Img => {
+            let src = text_content(element);
+            match sanitize_url(&src) {
+                Some(src) => {
+                    out.push_str("<img class=\"bbcode-img\" src=\"");
+                    escape_into(out, src);
+                    out.push_str("\" alt=\"\" loading=\"lazy\" referrerpolicy=\"no-referrer\" />");
+                }
+                None => escape_into(out, &src),
+            }
+        }
+        Tag::Quote => {
+            out.push_str("<blockquote class=\"bbcode-quote\">");
+            if let Some(author) = &element.argument {
+                out.push_str("<cite>");
+                escape_into(out, author);
+                out.push_str("</cite>");
+            }
+            write_nodes(out, &element.children);
+            out.push_str("</blockquote>");
+        }
+        Tag::Code => {
+            out.push_str("<pre class=\"bbcode-code\"><code>");
+            escape_into(out, &text_content(element));
+            out.push_str("</code></pre>");
+        }
+        Tag::Spoiler => {
+            out.push_str("<details class=\"bbcode-spoiler\"><summary>");
+            escape_into(out, element.argument.as_deref().unwrap_or("Spoiler"));
+            out.push_str("</summary>");
+            write_nodes(out, &element.children);
+            out.push_str("</details>");
+        }
+        Tag::List => match element.argument.as_deref() {
+            Some("1") => write_wrapped(out, "<ol>", element, "</ol>"),
+            _ => write_wrapped(out, "<ul>", element, "</ul>"),
+        },
+        Tag::ListItem => write_wrapped(out, "<li>", element, "</li>"),
+    }
+}
+
+/// Renders parsed BBCode as HTML.
+pub fn to_html(nodes: &[Node]) -> String {
+    let mut out = String::with_capacity(nodes.len() * 16);
+    write_nodes(&mut out, nodes);
+    out
+}
@@ -0,0 +1,12 @@
+//! BBCode formatting for user generated content.
+//! Input is parsed into a tree of allowed elements and written back out as HTML.
+//! Anything which is not an allowed tag is text, and all text is escaped,
+//! so user input can never produce markup we did not write ourselves.
+
+pub mod html;
+pub mod parse;
+
+/// Parses BBCode and renders it as HTML. The output is safe to include unescaped.
+pub fn render(input: &str) -> String {
+    html::to_html(&parse::parse(input))
+}
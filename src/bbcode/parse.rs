@@ -0,0 +1,327 @@
+//! BBCode parser.
+//! Tags are matched leniently: unclosed tags are closed at the end of input,
+//! misnested tags are closed as soon as an outer tag closes, and anything
+//! which is not a recognized tag is left as text.
+
+/// Deepest nesting of tags we build. Tags past this depth are left as text.
+pub const MAX_DEPTH: usize = 24;
+
+/// Longest tag we recognize, brackets and argument included.
+const MAX_TAG_LENGTH: usize = 512;
+
+/// Every tag we understand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tag {
+    Bold,
+    Italic,
+    Url,
+    Img,
+    Quote,
+    Code,
+    Spoiler,
+    List,
+    /// `[*]`, only meaningful inside a list.
+    ListItem,
+}
+
+impl Tag {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "b" => Some(Self::Bold),
+            "i" => Some(Self::Italic),
+            "url" => Some(Self::Url),
+            "img" => Some(Self::Img),
+            "quote" => Some(Self::Quote),
+            "code" => Some(Self::Code),
+            "spoiler" => Some(Self::Spoiler),
+            "list" => Some(Self::List),
+            "*" => Some(Self::ListItem),
+            _ => None,
+        }
+    }
+
+    /// Tags whose contents are kept exactly as written instead of being parsed.
+    fn is_verbatim(self) -> bool {
+        matches!(self, Self::Code | Self::Img)
+    }
+
+    /// Tags rendered as blocks. Line breaks directly around their start and end tags are dropped.
+    fn is_block(self) -> bool {
+        matches!(self, Self::Quote | Self::Code | Self::Spoiler | Self::List)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Node {
+    Text(String),
+    LineBreak,
+    Element(Element),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Element {
+    pub tag: Tag,
+    /// The value of `[tag=argument]`, without quotes.
+    pub argument: Option<String>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Token<'a> {
+    Text(&'a str),
+    LineBreak,
+    Open {
+        tag: Tag,
+        argument: Option<&'a str>,
+        raw: &'a str,
+    },
+    Close {
+        tag: Tag,
+        raw: &'a str,
+    },
+}
+
+impl<'a> Token<'a> {
+    /// The input this token was read from.
+    fn raw(&self) -> &'a str {
+        match self {
+            Self::Text(text) => text,
+            Self::LineBreak => "\n",
+            Self::Open { raw, .. } => raw,
+            Self::Close { raw, .. } => raw,
+        }
+    }
+}
+
+/// Reads the inside of `[...]` as a tag, if it is one we know.
+fn read_tag(raw: &str) -> Option<Token<'_>> {
+    let inner = &raw[1..raw.len() - 1];
+
+    if let Some(name) = inner.strip_prefix('/') {
+        return Tag::from_name(name).map(|tag| Token::Close { tag, raw });
+    }
+
+    let (name, argument) = match inner.split_once('=') {
+        Some((name, argument)) => {
+            let argument = argument.trim();
+            let argument = argument
+                .strip_prefix('"')
+                .and_then(|a| a.strip_suffix('"'))
+                .unwrap_or(argument);
+            (name, Some(argument).filter(|a| !a.is_empty()))
+        }
+        None => (inner, None),
+    };
+
+    Tag::from_name(name).map(|tag| Token::Open { tag, argument, raw })
+}
+
+fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = input[pos..].find(['[', '\n']) {
+        let at = pos + offset;
+
+        if input.as_bytes()[at] == b'\n' {
+            // Windows line endings arrive from some browsers.
+            let text_end = match input[..at].ends_with('\r') {
+                true => at - 1,
+                false => at,
+            };
+            if text_start < text_end {
+                tokens.push(Token::Text(&input[text_start..text_end]));
+            }
+            tokens.push(Token::LineBreak);
+            pos = at + 1;
+            text_start = pos;
+            continue;
+        }
+
+        let tag = input[at + 1..]
+            .find([']', '[', '\n'])
+            .map(|end| at + 1 + end)
+            .filter(|end| input.as_bytes()[*end] == b']' && end - at < MAX_TAG_LENGTH)
+            .and_then(|end| read_tag(&input[at..=end]));
+
+        match tag {
+            Some(token) => {
+                if text_start < at {
+                    tokens.push(Token::Text(&input[text_start..at]));
+                }
+                pos = at + token.raw().len();
+                text_start = pos;
+                tokens.push(token);
+            }
+            // Not a tag; the bracket stays part of the text.
+            None => pos = at + 1,
+        }
+    }
+
+    if text_start < input.len() {
+        tokens.push(Token::Text(&input[text_start..]));
+    }
+
+    tokens
+}
+
+/// Builds the tree. Elements are held on a stack until they are closed.
+struct Builder {
+    root: Vec<Node>,
+    stack: Vec<Element>,
+    /// Set after a block tag so the line break following it is dropped.
+    skip_break: bool,
+}
+
+impl Builder {
+    fn children(&mut self) -> &mut Vec<Node> {
+        match self.stack.last_mut() {
+            Some(element) => &mut element.children,
+            None => &mut self.root,
+        }
+    }
+
+    fn top(&self) -> Option<Tag> {
+        self.stack.last().map(|element| element.tag)
+    }
+
+    fn push_text(&mut self, text: &str) {
+        self.skip_break = false;
+        let children = self.children();
+        match children.last_mut() {
+            Some(Node::Text(last)) => last.push_str(text),
+            _ => children.push(Node::Text(text.to_owned())),
+        }
+    }
+
+    fn push_break(&mut self) {
+        if std::mem::take(&mut self.skip_break) {
+            return;
+        }
+        self.children().push(Node::LineBreak);
+    }
+
+    /// Drops a line break at the end of the current children.
+    fn trim_break(&mut self) {
+        let children = self.children();
+        if let Some(Node::LineBreak) = children.last() {
+            children.pop();
+        }
+    }
+
+    fn open(&mut self, tag: Tag, argument: Option<&str>) {
+        if tag.is_block() {
+            self.trim_break();
+        }
+        self.skip_break = tag.is_block();
+        self.stack.push(Element {
+            tag,
+            argument: argument.map(str::to_owned),
+            children: Vec::new(),
+        });
+    }
+
+    /// Closes every element from the top of the stack down to and including `depth`.
+    fn close_to(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            if self
+                .top()
+                .is_some_and(|tag| tag.is_block() || tag == Tag::ListItem)
+            {
+                self.trim_break();
+            }
+            let element = self.stack.pop().expect("stack is not empty");
+            self.skip_break = element.tag.is_block();
+            self.children().push(Node::Element(element));
+        }
+    }
+
+    fn find(&self, tag: Tag) -> Option<usize> {
+        self.stack.iter().rposition(|element| element.tag == tag)
+    }
+
+    /// Lists may only contain items. Stray content starts one, depth permitting.
+    fn ensure_list_item(&mut self) {
+        if self.top() == Some(Tag::List) && self.stack.len() < MAX_DEPTH {
+            self.open(Tag::ListItem, None);
+        }
+    }
+
+    fn push(&mut self, token: Token) {
+        // Nothing is parsed inside verbatim tags except their own closing tag.
+        if let Some(tag) = self.top().filter(|tag| tag.is_verbatim()) {
+            match token {
+                Token::Close { tag: close, .. } if close == tag => {
+                    self.close_to(self.stack.len() - 1)
+                }
+                Token::LineBreak if std::mem::take(&mut self.skip_break) => {}
+                token => self.push_text(token.raw()),
+            }
+            return;
+        }
+
+        match token {
+            Token::Text(text) => {
+                if self.top() == Some(Tag::List) && text.trim().is_empty() {
+                    return;
+                }
+                self.ensure_list_item();
+                self.push_text(text);
+            }
+            Token::LineBreak => {
+                if self.top() != Some(Tag::List) {
+                    self.push_break();
+                }
+            }
+            Token::Open {
+                tag: Tag::ListItem,
+                raw,
+                ..
+            } => match self.find(Tag::List) {
+                Some(list) if list + 1 < MAX_DEPTH => {
+                    self.close_to(list + 1);
+                    self.open(Tag::ListItem, None);
+                }
+                _ => self.push_text(raw),
+            },
+            Token::Open { tag, argument, raw } => {
+                // An implicit list item needs room as well.
+                let depth = match self.top() {
+                    Some(Tag::List) => self.stack.len() + 2,
+                    _ => self.stack.len() + 1,
+                };
+                if depth > MAX_DEPTH {
+                    self.push_text(raw);
+                } else {
+                    self.ensure_list_item();
+                    self.open(tag, argument);
+                }
+            }
+            Token::Close { tag, raw } => match self.find(tag) {
+                Some(depth) => self.close_to(depth),
+                None => self.push_text(raw),
+            },
+        }
+    }
+
+    fn finish(mut self) -> Vec<Node> {
+        self.close_to(0);
+        self.root
+    }
+}
+
+/// Parses BBCode into a tree of nodes.
+pub fn parse(input: &str) -> Vec<Node> {
+    let mut builder = Builder {
+        root: Vec::new(),
+        stack: Vec::new(),
+        skip_break: false,
+    };
+
+    for token in tokenize(input) {
+        builder.push(token);
+    }
+
+    builder.finish()
+}
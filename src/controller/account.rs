@@ -1,20 +1,73 @@
-use crate::middleware::{Context, Flash};
-use crate::model::User;
-use crate::util::{argon2_verify, normalize_username};
+use crate::filesystem::StorageBackend;
+use crate::filters;
+use crate::middleware::{Context, Flash, SessionEnded};
+use crate::model::user::ClaimError;
+use crate::model::{Group, User, UserExport, UserSession};
+use crate::session::Visitor;
+use crate::util::{
+    normalize_username, password_verify, validate_email, validate_username, PASSWORD_CIPHER,
+};
 use actix_web::cookie::Cookie;
-use actix_web::web::{Data, Form};
-use actix_web::{error, get, post, HttpRequest, Responder};
+use actix_web::http::header::USER_AGENT;
+use actix_web::web::{Data, Form, Path, Redirect};
+use actix_web::{error, get, post, HttpMessage, HttpRequest, Responder};
 use askama::Template;
 use scylla::Session;
 use serde::Deserialize;
+use uuid::Uuid;
 
 pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
-    conf.service(put_login)
+    conf.service(put_delete_account)
+        .service(put_export)
+        .service(put_login)
+        .service(put_logout)
         .service(put_register)
+        .service(put_rename)
+        .service(put_revoke_session)
+        .service(view_account)
+        .service(view_delete_account)
+        .service(view_export)
         .service(view_login)
         .service(view_register);
 }
 
+#[derive(Template)]
+#[template(path = "account/index.html")]
+pub struct AccountTemplate {
+    pub context: Context,
+    pub sessions: Vec<UserSession>,
+    pub exports: Vec<UserExport>,
+}
+
+/// Confirmation form for deleting an account, by its owner or staff.
+#[derive(Template)]
+#[template(path = "account/delete.html")]
+pub struct DeleteAccountTemplate {
+    pub context: Context,
+    pub title: String,
+    pub action: String,
+    /// Owners confirm with their password.
+    pub ask_password: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountForm {
+    password: Option<String>,
+    /// Checkbox. Present when the member's names should be forgotten too.
+    erase_name: Option<String>,
+}
+
+impl DeleteAccountForm {
+    pub fn erase_name(&self) -> bool {
+        self.erase_name.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameForm {
+    username: String,
+}
+
 #[derive(Debug, Deserialize, Default)]
 pub struct LoginForm {
     username: Option<String>,
@@ -57,12 +110,19 @@ pub async fn put_login(
             .await
             .map_err(error::ErrorInternalServerError)?
         {
-            Some(user) => {
-                if argon2_verify(&user.password, password)
+            Some(mut user) => {
+                if password_verify(&user.password_cipher, &user.password, password)
                     .map_err(error::ErrorInternalServerError)?
                 {
+                    // Passwords are only ever seen in plain on login, so this is the chance to upgrade them.
+                    if user.password_cipher != PASSWORD_CIPHER {
+                        if let Err(err) = user.set_password(scylla.to_owned(), password).await {
+                            log::error!("Failed to rehash password of user {}: {}", user.id, err);
+                        }
+                    }
+
                     let session_token = user
-                        .create_session(scylla)
+                        .create_session(scylla, user_agent(&req))
                         .await
                         .map_err(error::ErrorInternalServerError)?;
 
@@ -109,6 +169,37 @@ pub async fn put_login(
     .respond_to(&req))
 }
 
+#[post("/logout/")]
+pub async fn put_logout(
+    req: HttpRequest,
+    scylla: Data<Session>,
+    mut context: Context,
+) -> actix_web::Result<impl Responder> {
+    if let Some(session) = &context.visitor.session {
+        UserSession::delete(scylla, &session.id)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        req.extensions_mut().insert(SessionEnded);
+    }
+
+    context.visitor = Visitor::default();
+    context.groups = Group::guest_ids();
+
+    let mut http_resp = super::GenericTemplate {
+        context,
+        title: "Logged Out",
+        body: "You have been logged out.",
+    }
+    .respond_to(&req);
+
+    let mut session_cookie = Cookie::build("vf_session", "").path("/").finish();
+    session_cookie.make_removal();
+
+    http_resp.add_cookie(&session_cookie)?;
+
+    Ok(http_resp)
+}
+
 #[post("/register/")]
 pub async fn put_register(
     req: HttpRequest,
@@ -125,12 +216,31 @@ pub async fn put_register(
         password_confirm,
     } = form.0;
 
-    if username.is_none() {
+    // Browsers send empty fields as empty strings.
+    let username = username.filter(|username| !username.trim().is_empty());
+    let email = email.filter(|email| !email.trim().is_empty());
+    let password = password.filter(|password| !password.is_empty());
+
+    if let Some(username) = &username {
+        if let Err(message) = validate_username(username) {
+            valid = false;
+            context.jar.flash(Flash::Error, message);
+        }
+    } else {
         valid = false;
         context.jar.flash(Flash::Error, "A username is mandatory.");
-    } else if password.is_none() {
+    }
+
+    if let Some(email) = &email {
+        if let Err(message) = validate_email(email.trim()) {
+            valid = false;
+            context.jar.flash(Flash::Error, message);
+        }
+    }
+
+    if password.is_none() {
         valid = false;
-        context.jar.flash(Flash::Error, "a password is mandatory.");
+        context.jar.flash(Flash::Error, "A password is mandatory.");
     } else if password != password_confirm {
         valid = false;
         context
@@ -139,49 +249,55 @@ pub async fn put_register(
     }
 
     if valid {
-        let user = User::create(
+        match User::create(
             scylla.to_owned(),
             username.to_owned().unwrap(),
             email.to_owned(),
             password.to_owned().unwrap(),
         )
         .await
-        .map_err(error::ErrorInternalServerError)?;
-
-        let session_token = user
-            .create_session(scylla)
-            .await
-            .map_err(error::ErrorInternalServerError)?;
+        {
+            Ok(user) => {
+                let session_token = user
+                    .create_session(scylla, user_agent(&req))
+                    .await
+                    .map_err(error::ErrorInternalServerError)?;
 
-        let mut http_resp = super::GenericTemplate {
-            context,
-            title: "Registration Complete",
-            body: "Account has been succesfully registered.",
-        }
-        .respond_to(&req);
+                let mut http_resp = super::GenericTemplate {
+                    context,
+                    title: "Registration Complete",
+                    body: "Account has been succesfully registered.",
+                }
+                .respond_to(&req);
 
-        let session_cookie = Cookie::build("vf_session", session_token.to_string())
-            //.domain("www.rust-lang.org")
-            .path("/")
-            //.secure(true)
-            .http_only(true)
-            .finish();
+                let session_cookie = Cookie::build("vf_session", session_token.to_string())
+                    //.domain("www.rust-lang.org")
+                    .path("/")
+                    //.secure(true)
+                    .http_only(true)
+                    .finish();
 
-        http_resp.add_cookie(&session_cookie)?;
+                http_resp.add_cookie(&session_cookie)?;
 
-        Ok(http_resp)
-    } else {
-        Ok(RegisterTemplate {
-            context,
-            form: RegisterForm {
-                username,
-                email,
-                password: None,
-                password_confirm: None,
+                return Ok(http_resp);
+            }
+            Err(err) => match err.downcast_ref::<ClaimError>() {
+                Some(claim) => context.jar.flash(Flash::Error, &claim.to_string()),
+                None => return Err(error::ErrorInternalServerError(err)),
             },
         }
-        .respond_to(&req))
     }
+
+    Ok(RegisterTemplate {
+        context,
+        form: RegisterForm {
+            username,
+            email,
+            password: None,
+            password_confirm: None,
+        },
+    }
+    .respond_to(&req))
 }
 
 #[get("/login/")]
@@ -199,3 +315,243 @@ pub async fn view_register(context: Context) -> impl Responder {
         form: Default::default(),
     }
 }
+
+/// Returns the visitor's user id, or an error for guests.
+fn get_user_id(context: &Context) -> actix_web::Result<i64> {
+    Ok(context
+        .visitor
+        .user
+        .as_ref()
+        .ok_or_else(|| error::ErrorUnauthorized("You must be logged in."))?
+        .id)
+}
+
+async fn render_account_page(
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<AccountTemplate> {
+    let user_id = get_user_id(&context)?;
+    let (sessions, exports) = tokio::join!(
+        UserSession::fetch_for_user(scylla.clone(), user_id),
+        UserExport::fetch_for_user(scylla, user_id),
+    );
+
+    Ok(AccountTemplate {
+        context,
+        sessions: sessions.map_err(error::ErrorInternalServerError)?,
+        exports: exports.map_err(error::ErrorInternalServerError)?,
+    })
+}
+
+#[get("/account/")]
+pub async fn view_account(
+    scylla: Data<Session>,
+    context: Context,
+) -> actix_web::Result<impl Responder> {
+    render_account_page(context, scylla).await
+}
+
+#[post("/account/username")]
+pub async fn put_rename(
+    req: HttpRequest,
+    scylla: Data<Session>,
+    mut context: Context,
+    form: Form<RenameForm>,
+) -> actix_web::Result<impl Responder> {
+    let mut user = context
+        .visitor
+        .user
+        .to_owned()
+        .ok_or_else(|| error::ErrorUnauthorized("You must be logged in."))?;
+    let username = form.into_inner().username;
+
+    if username == user.username {
+        context
+            .jar
+            .flash(Flash::Error, "That is already your username.");
+    } else if let Err(message) = validate_username(&username) {
+        context.jar.flash(Flash::Error, message);
+    } else {
+        match user.rename(scylla.clone(), username).await {
+            Ok(()) => {
+                context.jar.flash(
+                    Flash::Success,
+                    &format!("Your username is now {}.", user.username),
+                );
+                context.visitor.user = Some(user);
+            }
+            Err(err) => match err.downcast_ref::<ClaimError>() {
+                Some(claim) => context.jar.flash(Flash::Error, &claim.to_string()),
+                None => return Err(error::ErrorInternalServerError(err)),
+            },
+        }
+    }
+
+    Ok(render_account_page(context, scylla).await?.respond_to(&req))
+}
+
+#[get("/account/delete")]
+pub async fn view_delete_account(context: Context) -> actix_web::Result<impl Responder> {
+    get_user_id(&context)?;
+
+    Ok(DeleteAccountTemplate {
+        context,
+        title: "Delete Account".to_owned(),
+        action: "/account/delete".to_owned(),
+        ask_password: true,
+    })
+}
+
+#[post("/account/delete")]
+pub async fn put_delete_account(
+    req: HttpRequest,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    mut context: Context,
+    form: Form<DeleteAccountForm>,
+) -> actix_web::Result<impl Responder> {
+    let user = context
+        .visitor
+        .user
+        .to_owned()
+        .ok_or_else(|| error::ErrorUnauthorized("You must be logged in."))?;
+
+    let verified = match &form.password {
+        Some(password) => password_verify(&user.password_cipher, &user.password, password)
+            .map_err(error::ErrorInternalServerError)?,
+        None => false,
+    };
+    if !verified {
+        context.jar.flash(Flash::Error, "Password is incorrect.");
+        return Ok(DeleteAccountTemplate {
+            context,
+            title: "Delete Account".to_owned(),
+            action: "/account/delete".to_owned(),
+            ask_password: true,
+        }
+        .respond_to(&req));
+    }
+
+    crate::account_deletion::request(scylla, storage, user.id, None, form.erase_name())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    req.extensions_mut().insert(SessionEnded);
+
+    context.visitor = Visitor::default();
+    context.groups = Group::guest_ids();
+
+    let mut http_resp = super::GenericTemplate {
+        context,
+        title: "Account Deleted",
+        body: "Your account is being deleted. You have been logged out.",
+    }
+    .respond_to(&req);
+
+    let mut session_cookie = Cookie::build("vf_session", "").path("/").finish();
+    session_cookie.make_removal();
+
+    http_resp.add_cookie(&session_cookie)?;
+
+    Ok(http_resp)
+}
+
+#[post("/account/export")]
+pub async fn put_export(
+    req: HttpRequest,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    mut context: Context,
+) -> actix_web::Result<impl Responder> {
+    let user_id = get_user_id(&context)?;
+    let exports = UserExport::fetch_for_user(scylla.clone(), user_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // One archive at a time is plenty.
+    if exports.iter().any(|export| export.finished_at.is_none()) {
+        context
+            .jar
+            .flash(Flash::Error, "An export is already being prepared.");
+    } else {
+        crate::export::request(scylla.clone(), storage, user_id)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        context.jar.flash(
+            Flash::Success,
+            "Your export is being prepared. It will be listed here once it is ready.",
+        );
+    }
+
+    Ok(render_account_page(context, scylla).await?.respond_to(&req))
+}
+
+#[get("/account/export/{export_id}")]
+pub async fn view_export(
+    req: HttpRequest,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    context: Context,
+    export_id: Path<String>,
+) -> actix_web::Result<impl Responder> {
+    let user_id = get_user_id(&context)?;
+    let export_id = Uuid::parse_str(&export_id.into_inner())
+        .map_err(|_| error::ErrorNotFound("Export not found."))?;
+
+    // Exports are looked up by owner, so other users' exports do not exist here.
+    match UserExport::fetch(scylla, user_id, export_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(export) if export.finished_at.is_some() && !export.is_expired() => {
+            super::asset::serve_stored_file(
+                &req,
+                storage,
+                &export.storage_key(),
+                "application/zip".parse().expect("valid mime"),
+            )
+            .await
+        }
+        _ => Err(error::ErrorNotFound("Export not found.")),
+    }
+}
+
+#[post("/account/sessions/{session_id}/revoke")]
+pub async fn put_revoke_session(
+    req: HttpRequest,
+    scylla: Data<Session>,
+    context: Context,
+    session_id: Path<String>,
+) -> actix_web::Result<impl Responder> {
+    let user_id = get_user_id(&context)?;
+    let session_id = Uuid::parse_str(&session_id.into_inner())
+        .map_err(|_| error::ErrorNotFound("Session not found."))?;
+
+    // Sessions which do not belong to the visitor look the same as ones which do not exist.
+    match UserSession::fetch(scylla.clone(), &session_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(session) if session.user_id == user_id => {
+            UserSession::delete(scylla, &session_id)
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+        }
+        _ => return Err(error::ErrorNotFound("Session not found.")),
+    }
+
+    // Revoking the current session logs the visitor out.
+    match &context.visitor.session {
+        Some(session) if session.id == session_id => {
+            req.extensions_mut().insert(SessionEnded);
+            Ok(Redirect::to("/").see_other())
+        }
+        _ => Ok(Redirect::to("/account/").see_other()),
+    }
+}
+
+/// Returns the user agent a request was made with.
+fn user_agent(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+}
@@ -1,15 +1,60 @@
+use crate::filesystem::thumbnail::THUMBNAIL_SIZES;
+use crate::filesystem::{self, StorageBackend};
 use crate::middleware::Context;
+use crate::model::attachment::AttachmentThumbnail;
+use crate::model::Attachment;
 use actix_files::NamedFile;
-use actix_web::{error, get, post, Error, HttpRequest, Responder, Result};
+use actix_multipart::form::tempfile::TempFile;
+use actix_multipart::form::text::Text;
+use actix_multipart::form::MultipartForm;
+use actix_web::http::header;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{error, get, post, Error, HttpRequest, HttpResponse, Responder, Result};
 use askama::Template;
+use scylla::Session;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
+    // The debug form must be registered before the {hash} route.
     conf.service(get_attachment_debug_form)
         .service(put_attachment)
+        .service(put_check_file)
+        .service(view_attachment)
+        .service(view_attachment_thumbnail)
         .service(view_public_file);
 }
 
+#[derive(Debug, MultipartForm)]
+pub struct UploadForm {
+    #[multipart(rename = "attachment")]
+    attachment: Vec<TempFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadedFile {
+    pub hash: String,
+    pub filename: String,
+    pub filesize: i64,
+    pub mime: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckFileForm {
+    hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckFileResponse {
+    exists: bool,
+    /// What the file is stored as, which differs from its hash if it was stripped of metadata.
+    hash: Option<String>,
+}
+
+/// How long links to files in remote storage remain valid.
+const PRESIGNED_URL_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Template)]
 #[template(path = "attachment/upload.html")]
 pub struct AttachmentDebugTemplate {
@@ -17,13 +62,257 @@ pub struct AttachmentDebugTemplate {
 }
 
 #[get("/attachments/upload")]
-async fn get_attachment_debug_form() -> Result<impl Responder> {
-    Ok("OK")
+async fn get_attachment_debug_form(context: Context) -> Result<impl Responder> {
+    Ok(AttachmentDebugTemplate { context })
 }
 
+/// Accepts one or more files and returns their hashes.
+/// The hashes can then be submitted alongside a post to attach them.
 #[post("/attachments/upload")]
-async fn put_attachment() -> Result<impl Responder> {
-    Ok("OK")
+async fn put_attachment(
+    context: Context,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    form: MultipartForm<UploadForm>,
+) -> Result<impl Responder> {
+    if !context.can("attachment.upload") {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to upload attachments.",
+        ));
+    }
+
+    let mut uploads = Vec::with_capacity(form.attachment.len());
+    for file in form.attachment.iter() {
+        // Browsers submit an empty part when no file is selected.
+        if file.size == 0 {
+            continue;
+        }
+
+        let attachment =
+            filesystem::ingest(scylla.clone(), storage.clone(), file.file.path().to_owned())
+                .await?;
+        uploads.push(UploadedFile {
+            hash: attachment.hash,
+            filename: file.file_name.to_owned().unwrap_or_default(),
+            filesize: attachment.filesize,
+            mime: attachment.mime,
+        });
+    }
+
+    Ok(Json(uploads))
+}
+
+/// Ingests files submitted with a post form and resolves ones uploaded ahead of time.
+/// Returns (hash, filename) pairs to link once the post exists.
+pub(super) async fn collect_form_attachments(
+    context: &Context,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    node_id: i64,
+    files: &[TempFile],
+    hashes: &[Text<String>],
+    names: &[Text<String>],
+) -> Result<Vec<(String, String)>> {
+    let mut attachments = Vec::new();
+    if files.is_empty() && hashes.is_empty() {
+        return Ok(attachments);
+    }
+
+    if !context.can_in("attachment.upload", node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to upload attachments.",
+        ));
+    }
+
+    for file in files.iter() {
+        // Browsers submit an empty part when no file is selected.
+        if file.size == 0 {
+            continue;
+        }
+
+        let attachment =
+            filesystem::ingest(scylla.clone(), storage.clone(), file.file.path().to_owned())
+                .await?;
+        attachments.push((
+            attachment.hash,
+            file.file_name.to_owned().unwrap_or_default(),
+        ));
+    }
+
+    for (i, hash) in hashes.iter().enumerate() {
+        let hash = hash.0.to_lowercase();
+        if !filesystem::is_valid_hash(&hash) {
+            return Err(error::ErrorBadRequest("Invalid file hash."));
+        }
+
+        let attachment = Attachment::fetch(scylla.clone(), &hash)
+            .await
+            .map_err(error::ErrorInternalServerError)?
+            .ok_or_else(|| error::ErrorBadRequest("Attachment has not been uploaded."))?;
+        let filename = names
+            .get(i)
+            .map(|name| name.0.to_owned())
+            .unwrap_or_default();
+        attachments.push((attachment.hash, filename));
+    }
+
+    Ok(attachments)
+}
+
+/// Links attachments from [collect_form_attachments] to a new post.
+pub(super) async fn attach_all_to_post(
+    scylla: Data<Session>,
+    post_id: i64,
+    attachments: &[(String, String)],
+) -> Result<()> {
+    for (hash, filename) in attachments.iter() {
+        let filename = if filename.is_empty() { hash } else { filename };
+        Attachment::attach_to_post(scylla.clone(), post_id, hash, filename)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+    }
+
+    Ok(())
+}
+
+/// Lets clients skip uploading a file we already have.
+/// Clients hash the file they have, which is found whether or not it was stripped when stored.
+#[post("/fs/check-file")]
+async fn put_check_file(
+    context: Context,
+    scylla: Data<Session>,
+    form: Json<CheckFileForm>,
+) -> Result<impl Responder> {
+    if !context.can("attachment.upload") {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to upload attachments.",
+        ));
+    }
+
+    let hash = form.hash.to_lowercase();
+    if !filesystem::is_valid_hash(&hash) {
+        return Err(error::ErrorBadRequest("Invalid file hash."));
+    }
+
+    let hash = Attachment::fetch_by_source(scylla, &hash)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .map(|attachment| attachment.hash);
+
+    Ok(Json(CheckFileResponse {
+        exists: hash.is_some(),
+        hash,
+    }))
+}
+
+/// Serves an attachment by its hash.
+#[get("/attachments/{hash}")]
+async fn view_attachment(
+    req: HttpRequest,
+    path: Path<String>,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+) -> Result<HttpResponse, Error> {
+    let hash = path.into_inner().to_lowercase();
+    if !filesystem::is_valid_hash(&hash) {
+        return Err(error::ErrorNotFound("File Not Found"));
+    }
+
+    let attachment = Attachment::fetch(scylla, &hash)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("File Not Found"))?;
+    let mime: mime::Mime = attachment
+        .mime
+        .parse()
+        .map_err(error::ErrorInternalServerError)?;
+
+    serve_stored_file(&req, storage, &hash, mime).await
+}
+
+/// Serves a thumbnail of an attachment.
+/// Images without a thumbnail yet are redirected to the original.
+#[get("/attachments/{hash}/thumb-{size}")]
+async fn view_attachment_thumbnail(
+    req: HttpRequest,
+    path: Path<(String, u32)>,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+) -> Result<HttpResponse, Error> {
+    let (hash, size) = path.into_inner();
+    let hash = hash.to_lowercase();
+    if !filesystem::is_valid_hash(&hash) || !THUMBNAIL_SIZES.contains(&size) {
+        return Err(error::ErrorNotFound("File Not Found"));
+    }
+
+    match AttachmentThumbnail::fetch(scylla.clone(), &hash, size as i32)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(thumbnail) => {
+            serve_stored_file(
+                &req,
+                storage,
+                &thumbnail.thumbnail_hash,
+                "image/webp".parse().expect("valid mime"),
+            )
+            .await
+        }
+        None => match Attachment::fetch(scylla, &hash)
+            .await
+            .map_err(error::ErrorInternalServerError)?
+        {
+            Some(attachment) if attachment.mime.starts_with("image/") => {
+                Ok(HttpResponse::TemporaryRedirect()
+                    .insert_header((header::LOCATION, format!("/attachments/{}", hash)))
+                    .finish())
+            }
+            _ => Err(error::ErrorNotFound("File Not Found")),
+        },
+    }
+}
+
+/// Responds with a file from storage.
+/// Remote storage is redirected to, local storage is served directly.
+pub(super) async fn serve_stored_file(
+    req: &HttpRequest,
+    storage: Data<dyn StorageBackend>,
+    key: &str,
+    mime: mime::Mime,
+) -> Result<HttpResponse, Error> {
+    if let Some(url) = storage
+        .presigned_url(key, PRESIGNED_URL_LIFETIME)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        return Ok(HttpResponse::TemporaryRedirect()
+            .insert_header((header::LOCATION, url))
+            .finish());
+    }
+
+    if let Some(path) = storage.local_path(key) {
+        return match NamedFile::open(path) {
+            Ok(file) => Ok(file
+                .set_content_type(mime)
+                .use_last_modified(true)
+                .into_response(req)),
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => Err(error::ErrorNotFound("File Not Found")),
+                _ => Err(error::ErrorInternalServerError(
+                    "Unexpected error trying to read file.",
+                )),
+            },
+        };
+    }
+
+    match storage
+        .get(key)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(bytes) => Ok(HttpResponse::Ok().content_type(mime).body(bytes)),
+        None => Err(error::ErrorNotFound("File Not Found")),
+    }
 }
 
 /// Dynamically access public files through the webserver.
@@ -1,9 +1,9 @@
-use crate::middleware::Context;
+use crate::middleware::{Context, Flash};
 use actix_web::body::{BoxBody, EitherBody};
 use actix_web::dev::ServiceResponse;
 use actix_web::http::{header, header::HeaderValue, StatusCode};
 use actix_web::middleware::ErrorHandlerResponse;
-use actix_web::{Error, Result};
+use actix_web::{Error, HttpResponse, Result};
 use askama_actix::Template;
 
 #[derive(Template)]
@@ -15,6 +15,15 @@ struct ErrorTemplate<'a> {
 }
 
 pub fn error_document<B>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
+    // Some errors are rendered by whoever raised them, with the visitor's context.
+    if res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|mime| mime.as_bytes().starts_with(b"text/html"))
+    {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
     let body = BoxBody::new(
         ErrorTemplate {
             context: Context::default(),
@@ -42,3 +51,24 @@ pub fn render_500<B>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>>
     log::error!("Custom backtrace: {}", std::backtrace::Backtrace::capture());
     error_document::<B>(res)
 }
+
+/// Rendered in place of a route when a form is submitted without a valid CSRF token.
+/// This is usually an old tab whose session has since ended, so say how to recover.
+pub fn csrf_document(mut context: Context) -> HttpResponse {
+    context.jar.flash(
+        Flash::Error,
+        "Your form could not be submitted because it has expired.",
+    );
+
+    HttpResponse::Forbidden()
+        .content_type("text/html; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(
+            super::GenericTemplate {
+                context,
+                title: "Form Expired",
+                body: "Go back, reload the page and try again. If this keeps happening, make sure cookies are enabled.",
+            }
+            .to_string(),
+        )
+}
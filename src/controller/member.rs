@@ -0,0 +1,124 @@
+use crate::filesystem::StorageBackend;
+use crate::filters;
+use crate::middleware::Context;
+use crate::model::{Group, User, UserDeletion, UserName};
+use actix_web::web::{Data, Form, Path, Redirect};
+use actix_web::{error, get, post, Responder};
+use askama::Template;
+use scylla::Session;
+
+pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
+    conf.service(put_delete_member)
+        .service(view_delete_member)
+        .service(view_member);
+}
+
+#[derive(Template)]
+#[template(path = "member.html")]
+pub struct MemberTemplate {
+    pub context: Context,
+    pub user_id: i64,
+    /// None if the member was deleted and their name erased.
+    pub name: Option<UserName>,
+    /// Names the member went by before their current one, newest first.
+    pub previous_names: Vec<UserName>,
+    pub deletion: Option<UserDeletion>,
+}
+
+#[get("/members/{user_id}/")]
+pub async fn view_member(
+    scylla: Data<Session>,
+    context: Context,
+    path: Path<i64>,
+) -> actix_web::Result<impl Responder> {
+    let user_id = path.into_inner();
+    let (names, deletion) = match tokio::join!(
+        UserName::fetch_history(scylla.clone(), user_id),
+        UserDeletion::fetch(scylla, user_id),
+    ) {
+        (Ok(names), Ok(deletion)) => (names, deletion),
+        (Err(err), _) => return Err(error::ErrorInternalServerError(err)),
+        (_, Err(err)) => return Err(error::ErrorInternalServerError(err)),
+    };
+
+    let mut previous_names = names.into_iter();
+    let name = previous_names.next();
+    if name.is_none() && deletion.is_none() {
+        return Err(error::ErrorNotFound("Member not found."));
+    }
+
+    Ok(MemberTemplate {
+        context,
+        user_id,
+        name,
+        previous_names: previous_names.collect(),
+        deletion,
+    })
+}
+
+#[get("/members/{user_id}/delete")]
+pub async fn view_delete_member(
+    scylla: Data<Session>,
+    context: Context,
+    path: Path<i64>,
+) -> actix_web::Result<impl Responder> {
+    let user = get_user_for_deleting(&context, scylla, path.into_inner()).await?;
+
+    Ok(super::account::DeleteAccountTemplate {
+        context,
+        title: format!("Delete {}", user.username),
+        action: format!("/members/{}/delete", user.id),
+        ask_password: false,
+    })
+}
+
+#[post("/members/{user_id}/delete")]
+pub async fn put_delete_member(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    context: Context,
+    path: Path<i64>,
+    form: Form<super::account::DeleteAccountForm>,
+) -> actix_web::Result<impl Responder> {
+    let user = get_user_for_deleting(&context, scylla.clone(), path.into_inner()).await?;
+    let requested_by = context.visitor.user.as_ref().map(|visitor| visitor.id);
+
+    crate::account_deletion::request(scylla, storage, user.id, requested_by, form.erase_name())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/members/{}/", user.id)).see_other())
+}
+
+/// Returns a user the visitor may delete.
+async fn get_user_for_deleting(
+    context: &Context,
+    scylla: Data<Session>,
+    user_id: i64,
+) -> actix_web::Result<User> {
+    if !context.can("user.delete") {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to delete members.",
+        ));
+    }
+
+    let user = User::fetch(scylla.clone(), user_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Member not found."))?;
+
+    // Staff cannot delete each other. Take the permission away first.
+    let groups = Group::fetch_ids_for_user(scylla, user.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if context
+        .permissions
+        .user_can(&groups, user.id, "user.delete")
+    {
+        return Err(error::ErrorForbidden(
+            "Members who may delete others cannot be deleted.",
+        ));
+    }
+
+    Ok(user)
+}
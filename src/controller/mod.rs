@@ -1,10 +1,13 @@
 use crate::middleware::Context;
 use askama::Template;
+use serde::Deserialize;
 
 pub mod account;
 pub mod asset;
 pub mod error;
+pub mod member;
 pub mod node;
+pub mod post;
 pub mod thread;
 
 /// Configures the web app by adding services from each web file.
@@ -15,7 +18,9 @@ pub fn configure(conf: &mut actix_web::web::ServiceConfig) {
     // Route resolution will stop at the first match.
     account::configure(conf);
     asset::configure(conf);
+    member::configure(conf);
     node::configure(conf);
+    post::configure(conf);
     thread::configure(conf);
 }
 
@@ -26,3 +31,30 @@ struct GenericTemplate<'a> {
     title: &'a str,
     body: &'a str,
 }
+
+/// Confirmation form for deleting a post or thread.
+#[derive(Template)]
+#[template(path = "delete.html")]
+struct DeleteTemplate {
+    context: Context,
+    title: &'static str,
+    action: String,
+    can_hard_delete: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteForm {
+    reason: Option<String>,
+    /// Checkbox. Present when content should be purged rather than hidden.
+    hard_delete: Option<String>,
+}
+
+impl DeleteForm {
+    fn reason(&self) -> Option<String> {
+        self.reason
+            .as_deref()
+            .map(str::trim)
+            .filter(|reason| !reason.is_empty())
+            .map(str::to_owned)
+    }
+}
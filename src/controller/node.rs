@@ -1,15 +1,59 @@
+use crate::filesystem::StorageBackend;
 use crate::filters;
 use crate::middleware::context::Context;
-use crate::model::{Node, Thread};
+use crate::middleware::Flash;
+use crate::model::deletion::DeletionKind;
+use crate::model::thread::get_bucket_for_timestamp;
+use crate::model::{Deletion, Node, Post, Thread, Ugc};
+use crate::util::{Paginator, PaginatorToHtml};
+use actix_multipart::form::tempfile::TempFile;
+use actix_multipart::form::text::Text;
+use actix_multipart::form::MultipartForm;
 use actix_web::web::{Data, Path, Redirect};
-use actix_web::{error, get, Responder};
+use actix_web::{error, get, post, HttpRequest, Responder};
 use askama::Template;
 use scylla::Session;
+use std::collections::HashMap;
 
 pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
-    conf.service(view_forum)
+    conf.service(put_thread)
+        .service(view_forum)
+        .service(view_forum_page)
         .service(view_forum_index)
-        .service(view_index);
+        .service(view_index)
+        .service(view_post_thread);
+}
+
+/// Longest thread title or subtitle we accept, in characters.
+pub const MAX_TITLE_LENGTH: usize = 150;
+/// Threads shown on a forum page.
+pub const THREADS_PER_PAGE: i32 = 20;
+
+pub fn get_pages_in_forum(cnt: i64) -> i64 {
+    ((std::cmp::max(1, cnt) - 1) / THREADS_PER_PAGE as i64) + 1
+}
+
+#[derive(Debug, Default, MultipartForm)]
+pub struct ThreadForm {
+    title: Option<Text<String>>,
+    subtitle: Option<Text<String>>,
+    content: Option<Text<String>>,
+    /// Files uploaded with the form itself.
+    attachment: Vec<TempFile>,
+    /// Files uploaded ahead of time, referenced by hash.
+    attachment_hash: Vec<Text<String>>,
+    /// Filenames for each attachment_hash, in the same order.
+    attachment_name: Vec<Text<String>>,
+}
+
+#[derive(Template)]
+#[template(path = "post_thread.html")]
+pub struct PostThreadTemplate {
+    pub context: Context,
+    pub node: Node,
+    pub title: String,
+    pub subtitle: String,
+    pub content: String,
 }
 
 #[derive(Template)]
@@ -17,7 +61,10 @@ pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
 pub struct ForumTemplate {
     pub context: Context,
     pub node: Node,
+    pub paginator: Paginator,
     pub threads: Vec<(Thread, i64, i64)>,
+    /// Deleted threads on this page. Only moderators see these.
+    pub deletions: HashMap<i64, Deletion>,
 }
 
 #[derive(Template)]
@@ -27,29 +74,63 @@ pub struct IndexTemplate {
     pub nodes: Vec<Node>,
 }
 
-#[get("/forums/{node_id}/")]
-async fn view_forum(
+async fn render_forum_page(
     context: Context,
     scylla: Data<Session>,
-    path: Path<i64>,
-) -> actix_web::Result<impl Responder> {
-    let node_id = path.into_inner();
-    let (node, threads) = match tokio::join!(
+    node_id: i64,
+    page: i64,
+) -> actix_web::Result<ForumTemplate> {
+    if !context.can_in("forum.view", node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view this forum.",
+        ));
+    }
+
+    let (node, mut threads, thread_count) = match tokio::join!(
         Node::fetch(scylla.clone(), node_id),
-        Thread::fetch_node_page(scylla.clone(), node_id, 1),
+        Thread::fetch_node_page(
+            scylla.clone(),
+            node_id,
+            page,
+            THREADS_PER_PAGE,
+            context.can_in("post.view_deleted", node_id),
+        ),
+        Node::fetch_thread_count(scylla.clone(), node_id),
     ) {
-        (Ok(node), Ok(threads)) => (
+        (Ok(node), Ok(threads), Ok(thread_count)) => (
             match node {
                 Some(node) => node,
                 None => return Err(error::ErrorNotFound("Forum not found.")),
             },
             threads,
+            thread_count.unwrap_or(0),
         ),
-        (Ok(_), Err(err)) => return Err(error::ErrorInternalServerError(err)),
-        (Err(err), Ok(_)) => return Err(error::ErrorInternalServerError(err)),
-        (Err(err), Err(_)) => return Err(error::ErrorInternalServerError(err)),
+        (Err(err), _, _) => return Err(error::ErrorInternalServerError(err)),
+        (_, Err(err), _) => return Err(error::ErrorInternalServerError(err)),
+        (_, _, Err(err)) => return Err(error::ErrorInternalServerError(err)),
     };
 
+    // Sticky threads are listed at the top of the first page instead of among the rest.
+    if page <= 1 {
+        let mut sticky = Thread::fetch_sticky(scylla.clone(), node_id)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        sticky.append(&mut threads);
+        threads = sticky;
+    }
+
+    let mut deletions = Deletion::fetch_many(
+        scylla.clone(),
+        DeletionKind::Thread,
+        threads.iter().map(|t| t.id).collect(),
+    )
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    if !context.can_in("post.view_deleted", node_id) {
+        threads.retain(|t| !deletions.contains_key(&t.id));
+        deletions.clear();
+    }
+
     let thread_ids: Vec<i64> = threads.iter().map(|t| t.id).collect();
     let (mut replies, mut views) = match tokio::join!(
         Thread::fetch_many_reply_count(scylla.clone(), thread_ids.to_owned()),
@@ -63,6 +144,11 @@ async fn view_forum(
 
     Ok(ForumTemplate {
         context,
+        paginator: Paginator {
+            base_url: format!("/forums/{}/", node_id),
+            this_page: page,
+            page_count: get_pages_in_forum(thread_count),
+        },
         node,
         threads: threads
             .into_iter()
@@ -75,6 +161,211 @@ async fn view_forum(
                 )
             })
             .collect(),
+        deletions,
+    })
+}
+
+#[get("/forums/{node_id}/")]
+async fn view_forum(
+    context: Context,
+    scylla: Data<Session>,
+    path: Path<i64>,
+) -> actix_web::Result<impl Responder> {
+    let node_id = path.into_inner();
+    render_forum_page(context, scylla, node_id, 1).await
+}
+
+#[get("/forums/{node_id}/page-{page}")]
+async fn view_forum_page(
+    req: HttpRequest,
+    path: Path<(i64, i64)>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let (node_id, page) = path.into_inner();
+    let threads = Node::fetch_thread_count(scylla.clone(), node_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .unwrap_or_default();
+    let max_page = get_pages_in_forum(threads);
+
+    if page <= 1 || max_page <= 1 {
+        Ok(Redirect::to(format!("/forums/{}/", node_id))
+            .see_other()
+            .respond_to(&req)
+            .map_into_left_body())
+    } else if max_page < page {
+        Ok(
+            Redirect::to(format!("/forums/{}/page-{}", node_id, max_page))
+                .see_other()
+                .respond_to(&req)
+                .map_into_left_body(),
+        )
+    } else {
+        Ok(render_forum_page(context, scylla, node_id, page)
+            .await?
+            .respond_to(&req)
+            .map_into_right_body())
+    }
+}
+
+/// Returns a node the visitor may create threads in, or an error.
+async fn get_node_for_posting(
+    context: &Context,
+    scylla: Data<Session>,
+    node_id: i64,
+) -> actix_web::Result<Node> {
+    if !context.can_in("forum.view", node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view this forum.",
+        ));
+    }
+    if !context.can_in("thread.create", node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to post threads in this forum.",
+        ));
+    }
+
+    Node::fetch(scylla, node_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Forum not found."))
+}
+
+#[post("/forums/{node_id}/post-thread")]
+async fn put_thread(
+    req: HttpRequest,
+    path: Path<i64>,
+    mut context: Context,
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    form: MultipartForm<ThreadForm>,
+) -> actix_web::Result<impl Responder> {
+    let node_id = path.into_inner();
+    let node = get_node_for_posting(&context, scylla.clone(), node_id).await?;
+
+    let text = |field: &Option<Text<String>>| {
+        field
+            .as_ref()
+            .map(|text| text.0.trim().to_owned())
+            .unwrap_or_default()
+    };
+    let title = text(&form.title);
+    let subtitle = text(&form.subtitle);
+    let content = text(&form.content);
+
+    let mut valid = true;
+    if title.is_empty() {
+        valid = false;
+        context.jar.flash(Flash::Error, "A title is mandatory.");
+    } else if title.chars().count() > MAX_TITLE_LENGTH
+        || subtitle.chars().count() > MAX_TITLE_LENGTH
+    {
+        valid = false;
+        context.jar.flash(
+            Flash::Error,
+            &format!("Titles may be at most {} characters.", MAX_TITLE_LENGTH),
+        );
+    }
+    if content.is_empty() {
+        valid = false;
+        context.jar.flash(Flash::Error, "A message is mandatory.");
+    }
+
+    if !valid {
+        return Ok(PostThreadTemplate {
+            context,
+            node,
+            title,
+            subtitle,
+            content,
+        }
+        .respond_to(&req)
+        .map_into_left_body());
+    }
+
+    // Files are checked before anything is written so a bad upload doesn't leave a thread behind.
+    let attachments = super::asset::collect_form_attachments(
+        &context,
+        scylla.clone(),
+        storage,
+        node_id,
+        &form.attachment,
+        &form.attachment_hash,
+        &form.attachment_name,
+    )
+    .await?;
+
+    let ugc = Ugc::create_for_visitor(scylla.clone(), &context.visitor, content)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let (thread_id, post_id) =
+        match tokio::join!(crate::util::snowflake_id(), crate::util::snowflake_id()) {
+            (Ok(thread_id), Ok(post_id)) => (thread_id, post_id),
+            (Err(err), _) => return Err(error::ErrorInternalServerError(err)),
+            (_, Err(err)) => return Err(error::ErrorInternalServerError(err)),
+        };
+    let user_id = context.visitor.user.as_ref().map(|u| u.id);
+
+    let post = Post {
+        id: post_id,
+        thread_id,
+        created_at: ugc.created_at,
+        user_id,
+        ugc_id: ugc.id,
+    };
+    post.insert(scylla.clone())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    super::asset::attach_all_to_post(scylla.clone(), post.id, &attachments).await?;
+
+    // The thread row is written last so it never lists without a first post.
+    let thread = Thread {
+        id: thread_id,
+        node_id: node.id,
+        bucket_id: get_bucket_for_timestamp(ugc.created_at.num_milliseconds()),
+        title,
+        subtitle: Some(subtitle).filter(|s| !s.is_empty()),
+        created_at: ugc.created_at,
+        first_post_id: post.id,
+        first_post_user_id: user_id,
+        last_post_id: post.id,
+        last_post_user_id: user_id,
+        locked: false,
+        sticky: false,
+        redirect_thread_id: None,
+    };
+    match tokio::join!(
+        thread.insert(scylla.clone()),
+        Thread::increment_reply_count(scylla.clone(), thread_id, 1),
+        Node::increment_thread_count(scylla.clone(), node.id, 1),
+    ) {
+        (Ok(_), Ok(_), Ok(_)) => {}
+        (Err(err), _, _) => return Err(error::ErrorInternalServerError(err)),
+        (_, Err(err), _) => return Err(error::ErrorInternalServerError(err)),
+        (_, _, Err(err)) => return Err(error::ErrorInternalServerError(err)),
+    }
+
+    Ok(Redirect::to(format!("/threads/{}/", thread_id))
+        .see_other()
+        .respond_to(&req)
+        .map_into_right_body())
+}
+
+#[get("/forums/{node_id}/post-thread")]
+async fn view_post_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let node = get_node_for_posting(&context, scylla, path.into_inner()).await?;
+
+    Ok(PostThreadTemplate {
+        context,
+        node,
+        title: Default::default(),
+        subtitle: Default::default(),
+        content: Default::default(),
     })
 }
 
@@ -85,7 +376,12 @@ async fn view_forum_index() -> impl Responder {
 
 #[get("/")]
 async fn view_index(context: Context, scylla: Data<Session>) -> impl Responder {
-    let nodes = Node::fetch_all(scylla).await.unwrap();
+    let nodes = Node::fetch_all(scylla)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|node| context.can_in("forum.view", node.id))
+        .collect();
 
     IndexTemplate { context, nodes }
 }
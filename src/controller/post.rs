@@ -0,0 +1,344 @@
+use crate::filters;
+use crate::middleware::{Context, Flash};
+use crate::model::deletion::DeletionKind;
+use crate::model::{Deletion, Post, Thread, Ugc, UserName};
+use actix_web::web::{Data, Form, Path, Query, Redirect};
+use actix_web::{error, get, post, route, HttpRequest, Responder};
+use askama::Template;
+use scylla::Session;
+use serde::Deserialize;
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashMap;
+
+pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
+    conf.service(put_delete_post)
+        .service(put_edit_post)
+        .service(put_restore_post)
+        .service(view_delete_post)
+        .service(view_edit_post)
+        .service(view_post_history);
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditPostForm {
+    content: Option<String>,
+}
+
+/// Revisions to compare, by timestamp. Defaults to the latest edit.
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    old: Option<i64>,
+    new: Option<i64>,
+}
+
+/// One line of a diff between revisions.
+#[derive(Debug)]
+pub struct DiffLine {
+    /// `delete`, `insert` or `equal`.
+    pub kind: &'static str,
+    pub text: String,
+}
+
+#[derive(Template)]
+#[template(path = "post_edit.html")]
+pub struct EditPostTemplate {
+    pub context: Context,
+    pub post: Post,
+    pub thread: Thread,
+    pub content: String,
+}
+
+#[derive(Template)]
+#[template(path = "post_history.html")]
+pub struct PostHistoryTemplate {
+    pub context: Context,
+    pub post: Post,
+    pub thread: Thread,
+    /// Newest first.
+    pub revisions: Vec<Ugc>,
+    pub users: HashMap<i64, UserName>,
+    pub old: i64,
+    pub new: i64,
+    pub diff: Vec<DiffLine>,
+}
+
+/// Returns a line diff between two revisions.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            kind: match change.tag() {
+                ChangeTag::Delete => "delete",
+                ChangeTag::Insert => "insert",
+                ChangeTag::Equal => "equal",
+            },
+            text: change.value().trim_end_matches(['\r', '\n']).to_owned(),
+        })
+        .collect()
+}
+
+/// Returns a post and its thread if the visitor can see them, and whether either is deleted.
+/// Deleted posts and threads are only visible to those who may view deleted content.
+async fn get_post_and_thread(
+    context: &Context,
+    scylla: Data<Session>,
+    post_id: i64,
+) -> actix_web::Result<(Post, Thread, bool)> {
+    let post = Post::fetch(scylla.clone(), post_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Post not found."))?;
+    let thread = super::thread::get_thread_or_error(scylla.clone(), &post.thread_id).await?;
+
+    if !context.can_in("forum.view", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view this thread.",
+        ));
+    }
+
+    let thread_deletion =
+        super::thread::get_thread_deletion(context, scylla.clone(), &thread).await?;
+    let post_deletion = Deletion::fetch(scylla, DeletionKind::Post, post.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if post_deletion.is_some() && !context.can_in("post.view_deleted", thread.node_id) {
+        return Err(error::ErrorNotFound("Post not found."));
+    }
+
+    let deleted = thread_deletion.is_some() || post_deletion.is_some();
+    Ok((post, thread, deleted))
+}
+
+/// Returns a post and its thread if the visitor may edit the post.
+async fn get_post_for_editing(
+    context: &Context,
+    scylla: Data<Session>,
+    post_id: i64,
+) -> actix_web::Result<(Post, Thread)> {
+    let (post, thread, deleted) = get_post_and_thread(context, scylla, post_id).await?;
+    if !context.can_edit_post(&post, thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to edit this post.",
+        ));
+    }
+    if deleted {
+        return Err(error::ErrorForbidden(
+            "This post has been deleted. Restore it before editing.",
+        ));
+    }
+    if thread.locked && !context.can_in("thread.moderate", thread.node_id) {
+        return Err(error::ErrorForbidden("This thread is locked."));
+    }
+
+    Ok((post, thread))
+}
+
+#[get("/posts/{post_id}/edit")]
+async fn view_edit_post(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let post_id = path.into_inner();
+    let (post, thread) = get_post_for_editing(&context, scylla.clone(), post_id).await?;
+    let ugc = Ugc::fetch(scylla, &post.ugc_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(EditPostTemplate {
+        context,
+        post,
+        thread,
+        content: ugc.map(|ugc| ugc.content).unwrap_or_default(),
+    })
+}
+
+/// Saves an edit as a new revision. Forms cannot send PATCH, so POST is accepted as well.
+#[route("/posts/{post_id}/edit", method = "PATCH", method = "POST")]
+async fn put_edit_post(
+    req: HttpRequest,
+    path: Path<i64>,
+    mut context: Context,
+    scylla: Data<Session>,
+    form: Form<EditPostForm>,
+) -> actix_web::Result<impl Responder> {
+    let post_id = path.into_inner();
+    let (post, thread) = get_post_for_editing(&context, scylla.clone(), post_id).await?;
+    let content = form
+        .content
+        .as_deref()
+        .unwrap_or_default()
+        .trim()
+        .to_owned();
+
+    if content.is_empty() {
+        context.jar.flash(Flash::Error, "A message is mandatory.");
+        return Ok(EditPostTemplate {
+            context,
+            post,
+            thread,
+            content,
+        }
+        .respond_to(&req)
+        .map_into_left_body());
+    }
+
+    Ugc::create_revision(scylla, post.ugc_id, &context.visitor, content)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(
+        Redirect::to(format!("/threads/{}/post-{}", thread.id, post.id))
+            .see_other()
+            .respond_to(&req)
+            .map_into_right_body(),
+    )
+}
+
+#[get("/posts/{post_id}/history")]
+async fn view_post_history(
+    path: Path<i64>,
+    query: Query<HistoryQuery>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let post_id = path.into_inner();
+    let (post, thread, _) = get_post_and_thread(&context, scylla.clone(), post_id).await?;
+    if !context.can_view_post_history(&post, thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view the history of this post.",
+        ));
+    }
+
+    let revisions = Ugc::fetch_revisions(scylla.clone(), &post.ugc_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let user_ids = revisions.iter().filter_map(|ugc| ugc.user_id).collect();
+    let users = UserName::fetch_many(scylla, user_ids)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // By default, compare the latest revision with the one before it.
+    let find = |timestamp: Option<i64>, default: usize| {
+        timestamp
+            .and_then(|t| {
+                revisions
+                    .iter()
+                    .position(|ugc| ugc.created_at.num_milliseconds() == t)
+            })
+            .or_else(|| Some(default).filter(|i| *i < revisions.len()))
+    };
+    let new = find(query.new, 0);
+    let old = find(query.old, 1).or(new);
+
+    let (old, new, diff) = match (old, new) {
+        (Some(old), Some(new)) => (
+            revisions[old].created_at.num_milliseconds(),
+            revisions[new].created_at.num_milliseconds(),
+            diff_lines(&revisions[old].content, &revisions[new].content),
+        ),
+        _ => (0, 0, Vec::new()),
+    };
+
+    Ok(PostHistoryTemplate {
+        context,
+        post,
+        thread,
+        revisions,
+        users,
+        old,
+        new,
+        diff,
+    })
+}
+
+/// Returns a post and its thread if the visitor may delete the post.
+async fn get_post_for_deleting(
+    context: &Context,
+    scylla: Data<Session>,
+    post_id: i64,
+) -> actix_web::Result<(Post, Thread)> {
+    let (post, thread, _) = get_post_and_thread(context, scylla, post_id).await?;
+    if !context.can_delete_post(&post, thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to delete this post.",
+        ));
+    }
+    if post.id == thread.first_post_id {
+        return Err(error::ErrorBadRequest(
+            "The first post of a thread cannot be deleted. Delete the thread instead.",
+        ));
+    }
+
+    Ok((post, thread))
+}
+
+#[get("/posts/{post_id}/delete")]
+async fn view_delete_post(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let post_id = path.into_inner();
+    let (post, thread) = get_post_for_deleting(&context, scylla, post_id).await?;
+    let can_hard_delete = context.can_in("post.hard_delete", thread.node_id);
+
+    Ok(super::DeleteTemplate {
+        context,
+        title: "Delete Post",
+        action: format!("/posts/{}/delete", post.id),
+        can_hard_delete,
+    })
+}
+
+#[post("/posts/{post_id}/delete")]
+async fn put_delete_post(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+    form: Form<super::DeleteForm>,
+) -> actix_web::Result<impl Responder> {
+    let post_id = path.into_inner();
+    let (post, thread) = get_post_for_deleting(&context, scylla.clone(), post_id).await?;
+    let user_id = context.visitor.user.as_ref().map(|u| u.id);
+
+    if form.hard_delete.is_some() {
+        if !context.can_in("post.hard_delete", thread.node_id) {
+            return Err(error::ErrorForbidden(
+                "You do not have permission to permanently delete posts.",
+            ));
+        }
+
+        post.purge(scylla, user_id, form.reason())
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        return Ok(Redirect::to(format!("/threads/{}/", thread.id)).see_other());
+    }
+
+    post.soft_delete(scylla, user_id, form.reason())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/post-{}", thread.id, post.id)).see_other())
+}
+
+#[post("/posts/{post_id}/restore")]
+async fn put_restore_post(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let post_id = path.into_inner();
+    let (post, thread, _) = get_post_and_thread(&context, scylla.clone(), post_id).await?;
+    if !context.can_in("post.delete_any", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to restore this post.",
+        ));
+    }
+
+    post.restore(scylla)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/post-{}", thread.id, post.id)).see_other())
+}
@@ -1,18 +1,88 @@
+use crate::filesystem::StorageBackend;
 use crate::filters;
-use crate::middleware::Context;
-use crate::model::{Node, Post, Thread, Ugc, User};
+use crate::middleware::{Context, Flash};
+use crate::model::attachment::PostAttachment;
+use crate::model::deletion::DeletionKind;
+use crate::model::{Attachment, Deletion, Node, Post, Thread, Ugc, UserName};
 use crate::util::{Paginator, PaginatorToHtml};
+use crate::views::{ViewCounter, Viewer};
+use actix_multipart::form::tempfile::TempFile;
 use actix_multipart::form::text::Text;
 use actix_multipart::form::MultipartForm;
-use actix_web::web::{Data, Path, Redirect};
-use actix_web::{error, get, post, HttpRequest, Responder};
+use actix_web::web::{Data, Form, Json, Path, Redirect};
+use actix_web::{error, get, post, Either, HttpRequest, Responder};
 use askama::Template;
 use scylla::Session;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Debug, Default, MultipartForm)]
 pub struct ReplyForm {
     content: Option<Text<String>>,
+    /// Files uploaded with the form itself.
+    attachment: Vec<TempFile>,
+    /// Files uploaded ahead of time, referenced by hash.
+    attachment_hash: Vec<Text<String>>,
+    /// Filenames for each attachment_hash, in the same order.
+    attachment_name: Vec<Text<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveThreadForm {
+    node_id: i64,
+    /// Checkbox. Leaves a stub in the old forum.
+    redirect: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeThreadForm {
+    /// The thread which receives the posts.
+    thread_id: i64,
+    /// Checkbox. Leaves this thread as a stub.
+    redirect: Option<String>,
+}
+
+#[derive(Debug, Default, MultipartForm)]
+pub struct SplitThreadForm {
+    title: Option<Text<String>>,
+    node_id: Option<Text<i64>>,
+    /// Posts which move to the new thread.
+    post_id: Vec<Text<i64>>,
+}
+
+/// Views counted but not yet written.
+#[derive(Debug, Serialize)]
+pub struct PendingViewsResponse {
+    pending_views: i64,
+    pending_threads: usize,
+}
+
+#[derive(Template)]
+#[template(path = "thread_move.html")]
+pub struct MoveThreadTemplate {
+    pub context: Context,
+    pub thread: Thread,
+    /// Forums the visitor may move the thread to.
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Template)]
+#[template(path = "thread_merge.html")]
+pub struct MergeThreadTemplate {
+    pub context: Context,
+    pub thread: Thread,
+}
+
+#[derive(Template)]
+#[template(path = "thread_split.html")]
+pub struct SplitThreadTemplate {
+    pub context: Context,
+    pub thread: Thread,
+    /// Forums the visitor may create the new thread in.
+    pub nodes: Vec<Node>,
+    pub posts: Vec<Post>,
+    pub users: HashMap<i64, UserName>,
+    pub title: String,
 }
 
 #[derive(Template)]
@@ -24,12 +94,29 @@ pub struct ThreadTemplate {
     pub posts: Vec<Post>,
     pub positions: HashMap<i64, i64>,
     pub ugcs: HashMap<i64, Ugc>,
-    pub users: HashMap<i64, User>,
+    pub users: HashMap<i64, UserName>,
+    pub attachments: HashMap<i64, Vec<PostAttachment>>,
+    /// Deleted posts on this page. Only moderators see these.
+    pub deletions: HashMap<i64, Deletion>,
+    pub thread_deletion: Option<Deletion>,
     pub paginator: Paginator,
 }
 
 pub(super) fn configure(conf: &mut actix_web::web::ServiceConfig) {
-    conf.service(put_reply)
+    conf.service(put_delete_thread)
+        .service(put_merge_thread)
+        .service(put_move_thread)
+        .service(put_repair_thread)
+        .service(put_reply)
+        .service(put_restore_thread)
+        .service(put_split_thread)
+        .service(put_thread_flag)
+        .service(view_delete_thread)
+        .service(view_merge_thread)
+        .service(view_move_thread)
+        .service(view_pending_views)
+        .service(view_post)
+        .service(view_split_thread)
         .service(view_thread)
         .service(view_thread_page);
 }
@@ -59,26 +146,57 @@ pub async fn get_thread_or_error(
     }
 }
 
+/// Returns the thread's deletion record, or an error if the visitor may not see deleted threads.
+pub(super) async fn get_thread_deletion(
+    context: &Context,
+    scylla: Data<Session>,
+    thread: &Thread,
+) -> actix_web::Result<Option<Deletion>> {
+    let deletion = Deletion::fetch(scylla, DeletionKind::Thread, thread.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    if deletion.is_some() && !context.can_in("post.view_deleted", thread.node_id) {
+        return Err(error::ErrorNotFound("Thread Not Found"));
+    }
+
+    Ok(deletion)
+}
+
 async fn render_thread_page(
     context: Context,
     scylla: Data<Session>,
+    views: Data<ViewCounter>,
+    viewer: Viewer,
     thread_id: i64,
     page: i64,
-) -> actix_web::Result<impl Responder> {
+) -> actix_web::Result<Either<Redirect, ThreadTemplate>> {
     let thread = get_thread_or_error(scylla.clone(), &thread_id).await?;
+    if !context.can_in("forum.view", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view this thread.",
+        ));
+    }
+    if let Some(redirect_thread_id) = thread.redirect_thread_id {
+        return Ok(Either::Left(
+            Redirect::to(format!("/threads/{}/", redirect_thread_id)).see_other(),
+        ));
+    }
+    let thread_deletion = get_thread_deletion(&context, scylla.clone(), &thread).await?;
 
-    let (node, (posts, positions), reply_count) = match tokio::join!(
+    // Deleted posts keep their positions, so pages are counted by the last position.
+    let (node, (mut posts, positions), last_position) = match tokio::join!(
         Node::fetch(scylla.clone(), thread.node_id),
-        Post::fetch_thread(scylla.clone(), thread_id, 1),
-        Thread::fetch_reply_count(scylla.clone(), thread_id),
+        Post::fetch_thread(scylla.clone(), thread_id, page, POSTS_PER_PAGE),
+        Post::fetch_last_position(scylla.clone(), thread_id),
     ) {
-        (Ok(node), Ok(posts), Ok(reply_count)) => (
+        (Ok(node), Ok(posts), Ok(last_position)) => (
             match node {
                 Some(node) => node,
                 None => return Err(error::ErrorNotFound("Thread Not Found")),
             },
             posts,
-            reply_count.unwrap_or(0),
+            last_position.unwrap_or(0),
         ),
         (Ok(_), Err(err), Ok(_)) => return Err(error::ErrorInternalServerError(err)),
         (Err(err), Ok(_), Ok(_)) => return Err(error::ErrorInternalServerError(err)),
@@ -89,24 +207,52 @@ async fn render_thread_page(
         (Err(err), Err(_), Err(_)) => return Err(error::ErrorInternalServerError(err)),
     };
 
-    let (ugcs, users) = match tokio::join!(
+    let mut deletions = Deletion::fetch_many(
+        scylla.clone(),
+        DeletionKind::Post,
+        posts.iter().map(|post| post.id).collect(),
+    )
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    if !context.can_in("post.view_deleted", thread.node_id) {
+        posts.retain(|post| !deletions.contains_key(&post.id));
+        deletions.clear();
+    }
+
+    let (ugcs, mut users, attachments) = match tokio::join!(
         Ugc::fetch_many_posts(scylla.clone(), &posts),
-        User::fetch_many_post_authors(scylla.clone(), &posts),
+        UserName::fetch_many_post_authors(scylla.clone(), &posts),
+        Attachment::fetch_many_posts(scylla.clone(), &posts),
     ) {
-        (Ok(ugcs), Ok(users)) => (ugcs, users),
-        (Ok(_), Err(err)) => return Err(error::ErrorInternalServerError(err)),
-        (Err(err), Ok(_)) => return Err(error::ErrorInternalServerError(err)),
-        (Err(err), Err(_)) => return Err(error::ErrorInternalServerError(err)),
+        (Ok(ugcs), Ok(users), Ok(attachments)) => (ugcs, users, attachments),
+        (Err(err), _, _) => return Err(error::ErrorInternalServerError(err)),
+        (_, Err(err), _) => return Err(error::ErrorInternalServerError(err)),
+        (_, _, Err(err)) => return Err(error::ErrorInternalServerError(err)),
     };
 
-    thread.bump_view_count(scylla.to_owned());
+    // Moderators see who deleted what.
+    let deleter_ids: Vec<i64> = deletions
+        .values()
+        .chain(thread_deletion.iter())
+        .filter_map(|deletion| deletion.deleted_by)
+        .filter(|id| !users.contains_key(id))
+        .collect();
+    if !deleter_ids.is_empty() {
+        users.extend(
+            UserName::fetch_many(scylla.clone(), deleter_ids)
+                .await
+                .map_err(error::ErrorInternalServerError)?,
+        );
+    }
+
+    views.record(viewer, thread.id);
 
-    Ok(ThreadTemplate {
+    Ok(Either::Right(ThreadTemplate {
         context,
         paginator: Paginator {
             base_url: format!("/threads/{}/", thread_id),
             this_page: page,
-            page_count: get_pages_in_thread(reply_count),
+            page_count: get_pages_in_thread(last_position),
         },
         node,
         thread,
@@ -114,7 +260,10 @@ async fn render_thread_page(
         positions,
         ugcs,
         users,
-    })
+        attachments,
+        deletions,
+        thread_deletion,
+    }))
 }
 
 #[post("/threads/{thread_id}/post-reply")]
@@ -122,15 +271,49 @@ async fn put_reply(
     path: Path<i64>,
     context: Context,
     scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
     form: MultipartForm<ReplyForm>,
 ) -> actix_web::Result<impl Responder> {
     let thread_id = path.into_inner();
     let thread = get_thread_or_error(scylla.clone(), &thread_id).await?;
+    if !context.can_in("thread.reply", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to reply to this thread.",
+        ));
+    }
+    if thread.redirect_thread_id.is_some() {
+        return Err(error::ErrorNotFound("Thread Not Found"));
+    }
+    if thread.locked && !context.can_in("thread.moderate", thread.node_id) {
+        return Err(error::ErrorForbidden("This thread is locked."));
+    }
+    // Replies would bump a deleted thread back into view, so not even moderators may reply.
+    if get_thread_deletion(&context, scylla.clone(), &thread)
+        .await?
+        .is_some()
+    {
+        return Err(error::ErrorForbidden(
+            "This thread has been deleted. Restore it before replying.",
+        ));
+    }
+
+    // Files are checked before anything is written so a bad upload doesn't leave a post behind.
+    let attachments = super::asset::collect_form_attachments(
+        &context,
+        scylla.clone(),
+        storage,
+        thread.node_id,
+        &form.attachment,
+        &form.attachment_hash,
+        &form.attachment_name,
+    )
+    .await?;
+
     let ugc = Ugc::create_for_visitor(
         scylla.clone(),
         &context.visitor,
         form.content.as_ref().expect("No post").0.to_string(),
-    ) // TODO: Sanitize
+    )
     .await
     .map_err(error::ErrorInternalServerError)?;
     let snowflake_id = crate::util::snowflake_id()
@@ -148,22 +331,92 @@ async fn put_reply(
         .await
         .map_err(error::ErrorInternalServerError)?;
 
+    super::asset::attach_all_to_post(scylla.clone(), post.id, &attachments).await?;
+
+    match tokio::join!(
+        Thread::bump(
+            scylla.clone(),
+            thread.id,
+            post.id,
+            post.user_id,
+            post.created_at.num_milliseconds(),
+        ),
+        Thread::increment_reply_count(scylla.clone(), thread.id, 1)
+    ) {
+        (Ok(_), Ok(_)) => {}
+        (Ok(_), Err(err)) => return Err(error::ErrorInternalServerError(err)),
+        (Err(err), Ok(_)) => return Err(error::ErrorInternalServerError(err)),
+        (Err(err), Err(_)) => return Err(error::ErrorInternalServerError(err)),
+    }
+
     let page = get_page_for_pos(pos);
     if page > 1 {
-        Ok(Redirect::to(format!("/threads/{}/page-{}", thread.id, page)).see_other())
+        Ok(Redirect::to(format!(
+            "/threads/{}/page-{}#post-{}",
+            thread.id, page, post.id
+        ))
+        .see_other())
     } else {
-        Ok(Redirect::to(format!("/threads/{}/", thread.id)).see_other())
+        Ok(Redirect::to(format!("/threads/{}/#post-{}", thread.id, post.id)).see_other())
+    }
+}
+
+/// Permalink to a post. Redirects to wherever the post currently appears.
+#[get("/threads/{thread_id}/post-{post_id}")]
+async fn view_post(
+    path: Path<(i64, i64)>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let (_, post_id) = path.into_inner();
+    // The thread in the link is ignored. Posts may have moved since it was shared.
+    let post = Post::fetch(scylla.clone(), post_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Post not found."))?;
+    let thread = get_thread_or_error(scylla.clone(), &post.thread_id).await?;
+    if !context.can_in("forum.view", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view this thread.",
+        ));
+    }
+    get_thread_deletion(&context, scylla.clone(), &thread).await?;
+    if !context.can_in("post.view_deleted", thread.node_id)
+        && Deletion::fetch(scylla.clone(), DeletionKind::Post, post.id)
+            .await
+            .map_err(error::ErrorInternalServerError)?
+            .is_some()
+    {
+        return Err(error::ErrorNotFound("Post not found."));
+    }
+
+    let pos = Post::fetch_position(scylla.clone(), thread.id, post.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Post not found."))?;
+    let page = get_page_for_pos(pos);
+    if page > 1 {
+        Ok(Redirect::to(format!(
+            "/threads/{}/page-{}#post-{}",
+            thread.id, page, post.id
+        ))
+        .see_other())
+    } else {
+        Ok(Redirect::to(format!("/threads/{}/#post-{}", thread.id, post.id)).see_other())
     }
 }
 
 #[get("/threads/{thread_id}/")]
 async fn view_thread(
+    req: HttpRequest,
     path: Path<i64>,
     context: Context,
     scylla: Data<Session>,
+    views: Data<ViewCounter>,
 ) -> actix_web::Result<impl Responder> {
     let thread_id = path.into_inner();
-    render_thread_page(context, scylla, thread_id, 1).await
+    let viewer = Viewer::from_request(&req, context.visitor.user.as_ref().map(|u| u.id));
+    render_thread_page(context, scylla, views, viewer, thread_id, 1).await
 }
 
 #[get("/threads/{thread_id}/page-{page}")]
@@ -172,13 +425,14 @@ async fn view_thread_page(
     path: Path<(i64, i64)>,
     context: Context,
     scylla: Data<Session>,
+    views: Data<ViewCounter>,
 ) -> actix_web::Result<impl Responder> {
     let (thread_id, page) = path.into_inner();
-    let replies = Thread::fetch_reply_count(scylla.clone(), thread_id)
+    let last_position = Post::fetch_last_position(scylla.clone(), thread_id)
         .await
         .map_err(error::ErrorInternalServerError)?
         .unwrap_or_default();
-    let max_page = get_page_for_pos(replies);
+    let max_page = get_page_for_pos(last_position);
 
     if page <= 1 || max_page <= 1 {
         Ok(Redirect::to(format!("/threads/{}/", thread_id))
@@ -193,9 +447,384 @@ async fn view_thread_page(
                 .map_into_left_body(),
         )
     } else {
-        Ok(render_thread_page(context, scylla, thread_id, page)
+        let viewer = Viewer::from_request(&req, context.visitor.user.as_ref().map(|u| u.id));
+        Ok(
+            render_thread_page(context, scylla, views, viewer, thread_id, page)
+                .await?
+                .respond_to(&req)
+                .map_into_right_body(),
+        )
+    }
+}
+
+/// Returns a thread if the visitor may delete it.
+async fn get_thread_for_deleting(
+    context: &Context,
+    scylla: Data<Session>,
+    thread_id: i64,
+) -> actix_web::Result<Thread> {
+    let thread = get_thread_or_error(scylla, &thread_id).await?;
+    if !context.can_in("post.delete_any", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to delete this thread.",
+        ));
+    }
+
+    Ok(thread)
+}
+
+#[get("/threads/{thread_id}/delete")]
+async fn view_delete_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_deleting(&context, scylla, thread_id).await?;
+    let can_hard_delete = context.can_in("post.hard_delete", thread.node_id);
+
+    Ok(super::DeleteTemplate {
+        context,
+        title: "Delete Thread",
+        action: format!("/threads/{}/delete", thread.id),
+        can_hard_delete,
+    })
+}
+
+#[post("/threads/{thread_id}/delete")]
+async fn put_delete_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+    form: Form<super::DeleteForm>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_deleting(&context, scylla.clone(), thread_id).await?;
+    let user_id = context.visitor.user.as_ref().map(|u| u.id);
+
+    if form.hard_delete.is_some() {
+        if !context.can_in("post.hard_delete", thread.node_id) {
+            return Err(error::ErrorForbidden(
+                "You do not have permission to permanently delete threads.",
+            ));
+        }
+
+        thread
+            .purge(scylla, user_id, form.reason())
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+    } else {
+        thread
+            .soft_delete(scylla, user_id, form.reason())
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+    }
+
+    Ok(Redirect::to(format!("/forums/{}/", thread.node_id)).see_other())
+}
+
+#[post("/threads/{thread_id}/restore")]
+async fn put_restore_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_deleting(&context, scylla.clone(), thread_id).await?;
+
+    thread
+        .restore(scylla)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/", thread.id)).see_other())
+}
+
+/// Returns a thread if the visitor may moderate it.
+async fn get_thread_for_moderating(
+    context: &Context,
+    scylla: Data<Session>,
+    thread_id: i64,
+) -> actix_web::Result<Thread> {
+    let thread = get_thread_or_error(scylla, &thread_id).await?;
+    if !context.can_in("thread.moderate", thread.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to moderate this thread.",
+        ));
+    }
+
+    Ok(thread)
+}
+
+/// Returns every forum the visitor may moderate threads in.
+async fn get_moderated_nodes(
+    context: &Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<Vec<Node>> {
+    let mut nodes = Node::fetch_all(scylla)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    nodes.retain(|node| context.can_in("thread.moderate", node.id));
+    nodes.sort_by_key(|node| node.display_order);
+
+    Ok(nodes)
+}
+
+#[post("/threads/{thread_id}/{action:(lock|unlock|stick|unstick)}")]
+async fn put_thread_flag(
+    path: Path<(i64, String)>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let (thread_id, action) = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+
+    match action.as_str() {
+        "lock" | "unlock" => {
+            let locked = action == "lock";
+            Thread::modify(scylla, thread.id, |t| t.locked = locked)
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+        }
+        _ => {
+            Thread::set_sticky(scylla, thread.id, action == "stick")
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+        }
+    }
+
+    Ok(Redirect::to(format!("/threads/{}/", thread.id)).see_other())
+}
+
+/// Renumbers a thread's posts and corrects its reply count.
+#[post("/threads/{thread_id}/repair")]
+async fn put_repair_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+
+    Thread::repair(scylla, thread.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/", thread.id)).see_other())
+}
+
+#[get("/threads/{thread_id}/move")]
+async fn view_move_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+    let mut nodes = get_moderated_nodes(&context, scylla).await?;
+    nodes.retain(|node| node.id != thread.node_id);
+
+    Ok(MoveThreadTemplate {
+        context,
+        thread,
+        nodes,
+    })
+}
+
+#[post("/threads/{thread_id}/move")]
+async fn put_move_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+    form: Form<MoveThreadForm>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+    if !context.can_in("thread.moderate", form.node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to move threads to this forum.",
+        ));
+    }
+    Node::fetch(scylla.clone(), form.node_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Forum not found."))?;
+
+    Thread::move_to_node(scylla, thread.id, form.node_id, form.redirect.is_some())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/", thread.id)).see_other())
+}
+
+#[get("/threads/{thread_id}/merge")]
+async fn view_merge_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla, thread_id).await?;
+
+    Ok(MergeThreadTemplate { context, thread })
+}
+
+#[post("/threads/{thread_id}/merge")]
+async fn put_merge_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+    form: Form<MergeThreadForm>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+    let target = get_thread_for_moderating(&context, scylla.clone(), form.thread_id).await?;
+    if thread.id == target.id {
+        return Err(error::ErrorBadRequest(
+            "A thread cannot be merged into itself.",
+        ));
+    }
+    if thread.redirect_thread_id.is_some() || target.redirect_thread_id.is_some() {
+        return Err(error::ErrorBadRequest("Redirects cannot be merged."));
+    }
+
+    thread
+        .merge_into(scylla, target.id, form.redirect.is_some())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/", target.id)).see_other())
+}
+
+async fn render_split_page(
+    context: Context,
+    scylla: Data<Session>,
+    thread: Thread,
+    title: String,
+) -> actix_web::Result<SplitThreadTemplate> {
+    let post_ids = Post::fetch_thread_ids(scylla.clone(), thread.id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let (nodes, posts) = match tokio::join!(
+        get_moderated_nodes(&context, scylla.clone()),
+        Post::fetch_many(scylla.clone(), post_ids),
+    ) {
+        (Ok(nodes), Ok(posts)) => (nodes, posts),
+        (Err(err), _) => return Err(err),
+        (_, Err(err)) => return Err(error::ErrorInternalServerError(err)),
+    };
+    let users = UserName::fetch_many_post_authors(scylla, &posts)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(SplitThreadTemplate {
+        context,
+        thread,
+        nodes,
+        posts,
+        users,
+        title,
+    })
+}
+
+#[get("/threads/{thread_id}/split")]
+async fn view_split_thread(
+    path: Path<i64>,
+    context: Context,
+    scylla: Data<Session>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+
+    render_split_page(context, scylla, thread, String::new()).await
+}
+
+#[post("/threads/{thread_id}/split")]
+async fn put_split_thread(
+    req: HttpRequest,
+    path: Path<i64>,
+    mut context: Context,
+    scylla: Data<Session>,
+    form: MultipartForm<SplitThreadForm>,
+) -> actix_web::Result<impl Responder> {
+    let thread_id = path.into_inner();
+    let thread = get_thread_for_moderating(&context, scylla.clone(), thread_id).await?;
+    let title = form
+        .title
+        .as_ref()
+        .map(|t| t.0.trim().to_owned())
+        .unwrap_or_default();
+    let node_id = form.node_id.as_ref().map_or(thread.node_id, |n| n.0);
+    let post_ids: Vec<i64> = form.post_id.iter().map(|p| p.0).collect();
+
+    let mut valid = true;
+    if title.is_empty() {
+        valid = false;
+        context.jar.flash(Flash::Error, "A title is mandatory.");
+    } else if title.chars().count() > super::node::MAX_TITLE_LENGTH {
+        valid = false;
+        context.jar.flash(
+            Flash::Error,
+            &format!(
+                "Titles may be at most {} characters.",
+                super::node::MAX_TITLE_LENGTH
+            ),
+        );
+    }
+    if post_ids.is_empty() {
+        valid = false;
+        context
+            .jar
+            .flash(Flash::Error, "Select at least one post to split off.");
+    } else if post_ids.contains(&thread.first_post_id) {
+        valid = false;
+        context
+            .jar
+            .flash(Flash::Error, "The first post cannot be split off.");
+    }
+
+    if !valid {
+        return Ok(render_split_page(context, scylla, thread, title)
             .await?
             .respond_to(&req)
-            .map_into_right_body())
+            .map_into_left_body());
+    }
+
+    if !context.can_in("thread.moderate", node_id) {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to create threads in this forum.",
+        ));
     }
+    Node::fetch(scylla.clone(), node_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Forum not found."))?;
+
+    let split = thread
+        .split(scylla, &post_ids, node_id, title)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(Redirect::to(format!("/threads/{}/", split.id))
+        .see_other()
+        .respond_to(&req)
+        .map_into_right_body())
+}
+
+/// Shows staff how many thread views are waiting to be written.
+#[get("/threads/pending-views")]
+async fn view_pending_views(
+    context: Context,
+    views: Data<ViewCounter>,
+) -> actix_web::Result<impl Responder> {
+    if !context.can("thread.moderate") {
+        return Err(error::ErrorForbidden(
+            "You do not have permission to view pending views.",
+        ));
+    }
+
+    Ok(Json(PendingViewsResponse {
+        pending_views: views.pending(),
+        pending_threads: views.pending_threads(),
+    }))
 }
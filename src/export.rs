@@ -0,0 +1,354 @@
+//! Personal data exports.
+//! Users may download an archive of what we hold about them: their profile, sessions, every UGC
+//! revision they wrote, and the files attached to their posts. Archives are built in the background,
+//! kept in attachment storage, and removed along with their download link once they expire.
+
+use crate::filesystem::StorageBackend;
+use crate::model::{Attachment, Group, Post, Ugc, User, UserExport, UserName, UserSession};
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::{Duration, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use scylla::Session;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Hours an archive can be downloaded for.
+static EXPORT_LIFETIME: Lazy<Duration> = Lazy::new(|| {
+    Duration::hours(
+        std::env::var("VF_EXPORT_LIFETIME")
+            .ok()
+            .and_then(|hours| hours.parse().ok())
+            .unwrap_or(72),
+    )
+});
+
+#[derive(Serialize)]
+struct ProfileRecord {
+    id: i64,
+    username: String,
+    email: Option<String>,
+    groups: Vec<i32>,
+    /// Every name the user went by, newest first.
+    names: Vec<NameRecord>,
+}
+
+#[derive(Serialize)]
+struct NameRecord {
+    username: String,
+    since: String,
+}
+
+#[derive(Serialize)]
+struct SessionRecord {
+    created_at: String,
+    last_seen_at: String,
+    user_agent: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RevisionRecord {
+    ugc_id: String,
+    created_at: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct PostRecord {
+    id: i64,
+    thread_id: i64,
+    created_at: String,
+    ugc_id: String,
+    attachments: Vec<AttachmentRecord>,
+}
+
+#[derive(Serialize)]
+struct AttachmentRecord {
+    hash: String,
+    filename: String,
+    filesize: Option<i64>,
+    mime: Option<String>,
+    /// Where the file is in this archive.
+    path: String,
+}
+
+/// Formats a timestamp for people and other software to read.
+fn timestamp(duration: Duration) -> String {
+    match Utc
+        .timestamp_millis_opt(duration.num_milliseconds())
+        .single()
+    {
+        Some(datetime) => datetime.to_rfc3339(),
+        None => duration.num_milliseconds().to_string(),
+    }
+}
+
+/// Starts building an archive for a user.
+pub async fn request(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    user_id: i64,
+) -> Result<UserExport> {
+    let export = UserExport::new(user_id, *EXPORT_LIFETIME);
+    export.insert(scylla.clone()).await?;
+
+    let mut building = export.clone();
+    tokio::spawn(async move {
+        if let Err(err) = build(scylla.clone(), storage, &mut building).await {
+            log::error!(
+                "Export {} of user {} failed: {:?}",
+                building.id,
+                user_id,
+                err
+            );
+            // Forget the request so the user can ask again.
+            if let Err(err) = building.delete(scylla).await {
+                log::error!("Failed to remove failed export {}: {:?}", building.id, err);
+            }
+        }
+    });
+
+    Ok(export)
+}
+
+async fn build(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    export: &mut UserExport,
+) -> Result<()> {
+    let path = crate::filesystem::tmp_dir().join(format!("export-{}.zip", export.id));
+    let result = write_archive(
+        scylla.clone(),
+        storage.clone(),
+        export.id,
+        export.user_id,
+        &path,
+    )
+    .await;
+    let result = match result {
+        Ok(()) => {
+            storage
+                .put(&export.storage_key(), &path, "application/zip")
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    let _ = tokio::fs::remove_file(&path).await;
+    result?;
+
+    if !export.finish(scylla).await? {
+        // Abandoned for taking too long and already cleaned up, so this copy is not linked.
+        storage.delete(&export.storage_key()).await?;
+        return Err(anyhow::anyhow!("Export was removed while being built"));
+    }
+    log::info!("Export {} of user {} is ready.", export.id, export.user_id);
+    Ok(())
+}
+
+/// Everything that goes into an archive, gathered before it is written.
+struct Contents {
+    profile: ProfileRecord,
+    sessions: Vec<SessionRecord>,
+    revisions: Vec<RevisionRecord>,
+    posts: Vec<PostRecord>,
+    /// Attached files by their path in the archive, and where to read them from.
+    files: Vec<(String, PathBuf)>,
+}
+
+async fn write_archive(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    export_id: Uuid,
+    user_id: i64,
+    path: &Path,
+) -> Result<()> {
+    // Attachments not on this machine are downloaded next to the archive and removed after.
+    let downloads = crate::filesystem::tmp_dir().join(format!("export-{}", export_id));
+    let result = async {
+        let contents = gather(scylla, storage, user_id, &downloads).await?;
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || write_zip(&path, contents)).await?
+    }
+    .await;
+
+    match tokio::fs::remove_dir_all(&downloads).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            log::warn!("Failed to remove {}: {:?}", downloads.display(), err)
+        }
+        _ => {}
+    }
+    result
+}
+
+async fn gather(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    user_id: i64,
+    downloads: &Path,
+) -> Result<Contents> {
+    let user = User::fetch(scylla.clone(), user_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} does not exist", user_id))?;
+    let (names, groups, sessions, revisions, posts) = tokio::try_join!(
+        UserName::fetch_history(scylla.clone(), user_id),
+        Group::fetch_ids_for_user(scylla.clone(), user_id),
+        UserSession::fetch_for_user(scylla.clone(), user_id),
+        Ugc::fetch_for_user(scylla.clone(), user_id),
+        Post::fetch_for_user(scylla.clone(), user_id),
+    )?;
+    let post_attachments = Attachment::fetch_many_posts(scylla.clone(), &posts).await?;
+
+    // Files are written once however many posts carry them.
+    let mut written = HashSet::new();
+    let mut files = Vec::new();
+    let mut records = Vec::with_capacity(posts.len());
+    for post in posts {
+        let mut attachments = Vec::new();
+        for post_attachment in post_attachments.get(&post.id).into_iter().flatten() {
+            let hash = &post_attachment.attachment_hash;
+            let path = format!("attachments/{}", hash);
+            let attachment = Attachment::fetch(scylla.clone(), hash).await?;
+
+            if written.insert(hash.to_owned()) {
+                match locate(&storage, hash, downloads).await? {
+                    Some(source) => files.push((path.clone(), source)),
+                    None => log::warn!("Attachment {} is missing from storage.", hash),
+                }
+            }
+
+            attachments.push(AttachmentRecord {
+                hash: hash.to_owned(),
+                filename: post_attachment.filename.to_owned(),
+                filesize: attachment.as_ref().map(|a| a.filesize),
+                mime: attachment.map(|a| a.mime),
+                path,
+            });
+        }
+
+        records.push(PostRecord {
+            id: post.id,
+            thread_id: post.thread_id,
+            created_at: timestamp(post.created_at),
+            ugc_id: post.ugc_id.to_string(),
+            attachments,
+        });
+    }
+
+    Ok(Contents {
+        profile: ProfileRecord {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            groups,
+            names: names
+                .into_iter()
+                .map(|name| NameRecord {
+                    username: name.username,
+                    since: timestamp(name.created_at),
+                })
+                .collect(),
+        },
+        sessions: sessions
+            .into_iter()
+            .map(|session| SessionRecord {
+                created_at: timestamp(session.created_at),
+                last_seen_at: timestamp(session.last_seen_at),
+                user_agent: session.user_agent,
+            })
+            .collect(),
+        revisions: revisions
+            .into_iter()
+            .map(|ugc| RevisionRecord {
+                ugc_id: ugc.id.to_string(),
+                created_at: timestamp(ugc.created_at),
+                content: ugc.content,
+            })
+            .collect(),
+        posts: records,
+        files,
+    })
+}
+
+/// Returns a path on this machine to read a stored file from, downloading it if need be.
+async fn locate(
+    storage: &Data<dyn StorageBackend>,
+    hash: &str,
+    downloads: &Path,
+) -> Result<Option<PathBuf>> {
+    if let Some(path) = storage.local_path(hash) {
+        return Ok(Some(path).filter(|path| path.is_file()));
+    }
+
+    match storage.get(hash).await? {
+        Some(bytes) => {
+            tokio::fs::create_dir_all(downloads).await?;
+            let path = downloads.join(hash);
+            tokio::fs::write(&path, bytes).await?;
+            Ok(Some(path))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Writes the archive. This blocks, so it is run off the async workers.
+fn write_zip(path: &Path, contents: Contents) -> Result<()> {
+    let json = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let file = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut zip = ZipWriter::new(File::create(path)?);
+
+    zip.start_file("profile.json", json)?;
+    serde_json::to_writer_pretty(&mut zip, &contents.profile)?;
+    zip.start_file("sessions.json", json)?;
+    serde_json::to_writer_pretty(&mut zip, &contents.sessions)?;
+    zip.start_file("revisions.json", json)?;
+    serde_json::to_writer_pretty(&mut zip, &contents.revisions)?;
+
+    for (path, source) in contents.files {
+        zip.start_file(path, file)?;
+        std::io::copy(&mut File::open(source)?, &mut zip)?;
+    }
+
+    zip.start_file("posts.json", json)?;
+    serde_json::to_writer_pretty(&mut zip, &contents.posts)?;
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Removes expired archives and their download links, and requests whose build was abandoned.
+/// Returns how many were removed.
+pub async fn clean_up(scylla: Data<Session>, storage: Data<dyn StorageBackend>) -> Result<usize> {
+    let expired = UserExport::fetch_expired(scylla.clone()).await?;
+
+    for export in &expired {
+        storage.delete(&export.storage_key()).await?;
+        export.delete(scylla.clone()).await?;
+    }
+
+    Ok(expired.len())
+}
+
+/// Removes expired archives in the background every `period`, starting now.
+pub fn spawn_cleanup(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    period: std::time::Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+            match clean_up(scylla.clone(), storage.clone()).await {
+                Ok(0) => {}
+                Ok(removed) => log::info!("Removed {} expired exports.", removed),
+                Err(err) => log::error!("Removing expired exports failed: {:?}", err),
+            }
+        }
+    });
+}
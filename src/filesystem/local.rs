@@ -0,0 +1,146 @@
+use super::StorageBackend;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Stores files in a directory on this machine.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Resolves a key to a path inside the root, refusing anything which could escape it.
+    fn key_path(&self, key: &str) -> Result<PathBuf> {
+        let key = Path::new(key);
+        if key.as_os_str().is_empty()
+            || key.components().any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!("Invalid storage key: {:?}", key));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn put(&self, key: &str, path: &Path, _mime: &str) -> Result<()> {
+        let dest = self.key_path(key)?;
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Copy beside the destination and rename so readers never see a partial file.
+        // Each writer gets its own copy, as the same file is often stored twice at once.
+        let name = dest
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let partial = dest.with_file_name(format!("{}.{}.partial", name, Uuid::new_v4()));
+        let result = async {
+            tokio::fs::copy(path, &partial).await?;
+            tokio::fs::rename(&partial, &dest).await
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        Ok(result?)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.key_path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.key_path(key)?).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(tokio::fs::try_exists(self.key_path(key)?).await?)
+    }
+
+    async fn presigned_url(&self, _key: &str, _expires_in: Duration) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.key_path(key).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalStorage, StorageBackend};
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_local_storage() {
+        let root = std::env::temp_dir().join(format!("vf-storage-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(&root);
+        let source = Path::new("src/test/static/text.png");
+
+        assert!(!storage.exists("text").await.unwrap());
+        assert!(storage.get("text").await.unwrap().is_none());
+
+        storage.put("text", source, "image/png").await.unwrap();
+        assert!(storage.exists("text").await.unwrap());
+        assert_eq!(
+            storage.get("text").await.unwrap().unwrap(),
+            std::fs::read(source).unwrap()
+        );
+        assert!(storage
+            .presigned_url("text", std::time::Duration::from_secs(60))
+            .await
+            .unwrap()
+            .is_none());
+
+        storage.delete("text").await.unwrap();
+        assert!(!storage.exists("text").await.unwrap());
+        // Deleting twice is fine.
+        storage.delete("text").await.unwrap();
+
+        // Keys may not escape the root.
+        assert!(storage.put("../text", source, "image/png").await.is_err());
+        assert!(storage.get("/etc/passwd").await.is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_concurrent_put() {
+        let root = std::env::temp_dir().join(format!("vf-storage-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(&root);
+        let source = Path::new("src/test/static/text.png");
+
+        let (a, b) = tokio::join!(
+            storage.put("text", source, "image/png"),
+            storage.put("text", source, "image/png"),
+        );
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(
+            storage.get("text").await.unwrap().unwrap(),
+            std::fs::read(source).unwrap()
+        );
+        // Only the stored file is left behind.
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
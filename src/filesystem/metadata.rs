@@ -0,0 +1,315 @@
+//! Metadata stripping.
+//! Phone photos carry EXIF (including GPS), XMP and IPTC blocks which must never reach other users.
+//! Images are rewritten chunk by chunk keeping only what is needed to display them.
+//! WebM is remuxed by ffmpeg, which drops container tags, chapters and attachments.
+
+use super::{ffmpeg, Error};
+use std::path::Path;
+
+/// EXIF tag for image orientation. The only EXIF value we keep, so photos are not displayed sideways.
+const EXIF_ORIENTATION: u16 = 0x0112;
+
+/// PNG chunks needed to display the image correctly. Everything else is dropped.
+const PNG_CHUNK_ALLOWLIST: &[&[u8; 4]] = &[
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"bKGD", b"pHYs", b"acTL", b"fcTL", b"fdAT",
+];
+
+/// WebP chunks needed to display the image correctly. Everything else is dropped.
+const WEBP_CHUNK_ALLOWLIST: &[&[u8; 4]] = &[
+    b"VP8 ", b"VP8L", b"VP8X", b"ALPH", b"ANIM", b"ANMF", b"ICCP",
+];
+
+/// VP8X flags announcing EXIF and XMP chunks.
+const WEBP_VP8X_EXIF: u8 = 0x08;
+const WEBP_VP8X_XMP: u8 = 0x04;
+
+fn malformed(kind: &str) -> Error {
+    Error::InvalidMedia(format!("malformed {} structure", kind))
+}
+
+/// Writes a copy of the file without metadata to `dest`. Slow and blocking!
+pub fn strip(path: &Path, mime: &str, dest: &Path) -> Result<(), Error> {
+    let stripper: fn(&[u8]) -> Result<Vec<u8>, Error> = match mime {
+        "video/webm" => return remux_webm(path, dest),
+        "image/jpeg" => strip_jpeg,
+        "image/png" => strip_png,
+        "image/webp" => strip_webp,
+        // GIF has no EXIF. Comment blocks are plain text the uploader put there.
+        _ => {
+            std::fs::copy(path, dest)?;
+            return Ok(());
+        }
+    };
+
+    let bytes = std::fs::read(path)?;
+    std::fs::write(dest, stripper(&bytes)?)?;
+    Ok(())
+}
+
+/// Reads the orientation from an EXIF (APP1) payload.
+pub fn read_exif_orientation(payload: &[u8]) -> Option<u16> {
+    let tiff = payload.strip_prefix(b"Exif\0\0")?;
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let bytes: [u8; 2] = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let u32_at = |at: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    for i in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        // SHORT with a count of 1; the value sits in the first two bytes of the value field.
+        if u16_at(entry)? == EXIF_ORIENTATION && u16_at(entry + 2)? == 3 {
+            return Some(u16_at(entry + 8)?).filter(|o| (1..=8).contains(o));
+        }
+    }
+
+    None
+}
+
+/// Builds an APP1 segment holding nothing but the orientation.
+fn build_orientation_app1(orientation: u16) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(32);
+    payload.extend_from_slice(b"Exif\0\0");
+    // Big-endian TIFF header, IFD0 immediately after.
+    payload.extend_from_slice(b"MM\0\x2A\0\0\0\x08");
+    payload.extend_from_slice(&1u16.to_be_bytes());
+    payload.extend_from_slice(&EXIF_ORIENTATION.to_be_bytes());
+    payload.extend_from_slice(&3u16.to_be_bytes());
+    payload.extend_from_slice(&1u32.to_be_bytes());
+    payload.extend_from_slice(&orientation.to_be_bytes());
+    payload.extend_from_slice(&[0, 0]);
+    // No next IFD.
+    payload.extend_from_slice(&0u32.to_be_bytes());
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    segment.extend_from_slice(&payload);
+    segment
+}
+
+/// Rewrites a JPEG without APPn, COM or trailing data.
+/// JFIF, ICC profiles and Adobe color transforms are kept; EXIF is reduced to the orientation.
+pub fn strip_jpeg(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err(malformed("JPEG"));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&[0xFF, 0xD8]);
+    let mut pos = 2;
+
+    loop {
+        // Markers may be preceded by any number of fill bytes.
+        while bytes.get(pos) == Some(&0xFF) && bytes.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if bytes.get(pos) != Some(&0xFF) {
+            return Err(malformed("JPEG"));
+        }
+        let marker = *bytes.get(pos + 1).ok_or_else(|| malformed("JPEG"))?;
+
+        match marker {
+            // EOI. Anything after it is dropped.
+            0xD9 => {
+                out.extend_from_slice(&[0xFF, 0xD9]);
+                return Ok(out);
+            }
+            // Standalone markers without a length.
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&bytes[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes(
+            bytes
+                .get(pos + 2..pos + 4)
+                .ok_or_else(|| malformed("JPEG"))?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let end = pos + 2 + length;
+        if length < 2 || end > bytes.len() {
+            return Err(malformed("JPEG"));
+        }
+        let segment = &bytes[pos..end];
+        let payload = &bytes[pos + 4..end];
+
+        let keep = match marker {
+            0xE0 => payload.starts_with(b"JFIF\0"),
+            0xE1 => {
+                if let Some(orientation) = read_exif_orientation(payload) {
+                    out.extend_from_slice(&build_orientation_app1(orientation));
+                }
+                false
+            }
+            0xE2 => payload.starts_with(b"ICC_PROFILE\0"),
+            0xEE => payload.starts_with(b"Adobe"),
+            0xE3..=0xED | 0xEF | 0xFE => false,
+            _ => true,
+        };
+        if keep {
+            out.extend_from_slice(segment);
+        }
+        pos = end;
+
+        // SOS is followed by entropy-coded data which runs until the next real marker.
+        if marker == 0xDA {
+            let start = pos;
+            while pos + 1 < bytes.len() {
+                if bytes[pos] == 0xFF && !matches!(bytes[pos + 1], 0x00 | 0xD0..=0xD7 | 0xFF) {
+                    break;
+                }
+                pos += 1;
+            }
+            if pos + 1 >= bytes.len() {
+                return Err(malformed("JPEG"));
+            }
+            out.extend_from_slice(&bytes[start..pos]);
+        }
+    }
+}
+
+/// Rewrites a PNG keeping only chunks in [PNG_CHUNK_ALLOWLIST].
+pub fn strip_png(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !bytes.starts_with(SIGNATURE) {
+        return Err(malformed("PNG"));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+
+    loop {
+        let length = u32::from_be_bytes(
+            bytes
+                .get(pos..pos + 4)
+                .ok_or_else(|| malformed("PNG"))?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        // Length, type, data, CRC.
+        let end = pos + 12 + length;
+        if end > bytes.len() {
+            return Err(malformed("PNG"));
+        }
+        let kind = &bytes[pos + 4..pos + 8];
+
+        if PNG_CHUNK_ALLOWLIST.iter().any(|allowed| *allowed == kind) {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+
+        // Anything after IEND is dropped.
+        if kind == b"IEND" {
+            return Ok(out);
+        }
+    }
+}
+
+/// Rewrites a WebP keeping only chunks in [WEBP_CHUNK_ALLOWLIST].
+pub fn strip_webp(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err(malformed("WebP"));
+    }
+    let riff_end = 8 + u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    if riff_end > bytes.len() {
+        return Err(malformed("WebP"));
+    }
+
+    let mut chunks = Vec::with_capacity(bytes.len());
+    let mut pos = 12;
+
+    while pos < riff_end {
+        let header = bytes.get(pos..pos + 8).ok_or_else(|| malformed("WebP"))?;
+        let kind = &header[0..4];
+        let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        // Chunks are padded to an even length.
+        let end = pos + 8 + length + (length & 1);
+        if end > riff_end {
+            return Err(malformed("WebP"));
+        }
+
+        if WEBP_CHUNK_ALLOWLIST.iter().any(|allowed| *allowed == kind) {
+            let start = chunks.len();
+            chunks.extend_from_slice(&bytes[pos..end]);
+            if kind == b"VP8X" && length > 0 {
+                chunks[start + 8] &= !(WEBP_VP8X_EXIF | WEBP_VP8X_XMP);
+            }
+        }
+        pos = end;
+    }
+
+    let mut out = Vec::with_capacity(chunks.len() + 12);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&chunks);
+    Ok(out)
+}
+
+/// Copies the audio and video streams of a WebM into a fresh container, leaving tags, chapters and attachments behind.
+/// Stream durations are enforced here as well; headers can lie about them.
+pub fn remux_webm(path: &Path, dest: &Path) -> Result<(), Error> {
+    let invalid = |err: ffmpeg::Error| Error::InvalidMedia(err.to_string());
+
+    let mut input = ffmpeg::format::input(&path.to_owned()).map_err(invalid)?;
+    let mut output = ffmpeg::format::output_as(&dest.to_owned(), "webm").map_err(invalid)?;
+
+    let mut input_time_bases = Vec::with_capacity(input.nb_streams() as usize);
+    for stream in input.streams() {
+        input_time_bases.push(stream.time_base());
+        let mut out_stream = output
+            .add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::None))
+            .map_err(invalid)?;
+        out_stream.set_parameters(stream.parameters());
+    }
+
+    output.write_header().map_err(invalid)?;
+
+    for (stream, mut packet) in input.packets() {
+        let index = stream.index();
+        let time_base = input_time_bases[index];
+
+        if let Some(pts) = packet.pts() {
+            let seconds = pts as f64 * f64::from(time_base);
+            if seconds > super::validate::MAX_DURATION_SECS as f64 {
+                return Err(Error::InvalidMedia(format!(
+                    "longer than {} seconds",
+                    super::validate::MAX_DURATION_SECS
+                )));
+            }
+        }
+
+        let out_time_base = output
+            .stream(index)
+            .ok_or_else(|| Error::InvalidMedia("stream mismatch".to_owned()))?
+            .time_base();
+        packet.rescale_ts(time_base, out_time_base);
+        packet.set_position(-1);
+        packet.set_stream(index);
+        packet.write_interleaved(&mut output).map_err(invalid)?;
+    }
+
+    output.write_trailer().map_err(invalid)?;
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Attachment ingestion and storage.
+//! Uploaded files are sniffed with infer, validated with ffmpeg, stripped of metadata,
+//! hashed with blake3, and stored once per unique hash in a [StorageBackend].
+
+extern crate ffmpeg_the_third as ffmpeg;
+
+mod local;
+pub mod metadata;
+mod s3;
+mod storage;
+pub mod thumbnail;
+pub mod validate;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+pub use storage::{from_env, StorageBackend};
+
+use crate::model::Attachment;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use scylla::Session;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Attachment ingestion errors.
+#[derive(Debug)]
+pub enum Error {
+    /// The file is not a type we accept.
+    UnsupportedType(String),
+    /// The file claims to be a type we accept but cannot be read as one.
+    InvalidMedia(String),
+    /// Failures unrelated to the file itself (disk, database).
+    Internal(anyhow::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedType(mime) => write!(f, "Files of type {} are not allowed.", mime),
+            Self::InvalidMedia(reason) => write!(f, "File could not be read: {}", reason),
+            Self::Internal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl actix_web::ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnsupportedType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::InvalidMedia(_) => StatusCode::BAD_REQUEST,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            // Don't leak disk or database details to the client.
+            Self::Internal(err) => {
+                log::error!("Attachment ingestion failed: {:?}", err);
+                HttpResponse::InternalServerError().body("Unexpected error storing file.")
+            }
+            _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Internal(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+/// Facts about an uploaded file gathered before it is stored.
+#[derive(Debug)]
+pub struct FileInfo {
+    pub hash: String,
+    /// Hash of the file as it was uploaded, before it was stripped.
+    pub source_hash: String,
+    pub mime: String,
+    pub filesize: i64,
+    /// The sanitized copy which is stored. Removed by whoever stores it.
+    pub path: PathBuf,
+}
+
+/// Initializes ffmpeg. Must be called once before any media is validated.
+pub fn init() {
+    ffmpeg::init().expect("ffmpeg failed to initialize");
+}
+
+/// Directory scratch files are written to.
+pub fn tmp_dir() -> PathBuf {
+    std::env::var("VF_TMP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir())
+}
+
+/// Returns true if the string looks like a blake3 hash.
+pub fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == blake3::OUT_LEN * 2 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hashes a file's contents with blake3.
+pub fn hash_file(path: &Path) -> std::io::Result<blake3::Hash> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Sniffs, validates, strips and hashes a file. Slow and blocking!
+/// The hash is of the stripped copy. The file the client sent is hashed too, so they can be linked.
+pub fn inspect(path: &Path) -> Result<FileInfo, Error> {
+    let mime = infer::get_from_path(path)?
+        .map(|kind| kind.mime_type().to_owned())
+        .ok_or_else(|| Error::UnsupportedType("unknown".to_owned()))?;
+
+    validate::validate(path, &mime, std::fs::metadata(path)?.len())?;
+    let source_hash = hash_file(path)?.to_string();
+
+    let clean = tmp_dir().join(format!("{}.clean", uuid::Uuid::new_v4()));
+    let result = metadata::strip(path, &mime, &clean).and_then(|_| {
+        Ok(FileInfo {
+            hash: hash_file(&clean)?.to_string(),
+            source_hash,
+            mime,
+            filesize: std::fs::metadata(&clean)?.len() as i64,
+            path: clean.to_owned(),
+        })
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&clean);
+    }
+    result
+}
+
+/// Validates, stores and records an uploaded file.
+/// Files we have already stored are deduplicated by their hash.
+pub async fn ingest(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    path: PathBuf,
+) -> Result<Attachment, Error> {
+    let info = {
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || inspect(&path))
+            .await
+            .map_err(anyhow::Error::new)??
+    };
+
+    let stored = async {
+        if !storage.exists(&info.hash).await? {
+            storage.put(&info.hash, &info.path, &info.mime).await?;
+            thumbnail::spawn(scylla.clone(), storage.clone(), info.hash.to_owned());
+        }
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+    tokio::fs::remove_file(&info.path).await?;
+    stored?;
+
+    let attachment =
+        Attachment::insert_or_touch(scylla.clone(), &info.hash, info.filesize, &info.mime).await?;
+    if info.source_hash != info.hash {
+        Attachment::insert_source(scylla, &info.source_hash, &info.hash).await?;
+    }
+
+    Ok(attachment)
+}
@@ -0,0 +1,98 @@
+use super::StorageBackend;
+use anyhow::Result;
+use async_trait::async_trait;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use std::path::Path;
+use std::time::Duration;
+
+/// Stores files in an S3-compatible bucket (AWS, MinIO, etc).
+pub struct S3Storage {
+    bucket: Bucket,
+}
+
+impl S3Storage {
+    pub fn new(bucket: Bucket) -> Self {
+        Self { bucket }
+    }
+
+    /// Builds a bucket from the VF_AWS_* environmental variables.
+    pub fn new_from_env() -> Result<Self> {
+        let var = |key: &str| std::env::var(key).map_err(|_| anyhow::anyhow!("{} is unset", key));
+
+        let region = Region::Custom {
+            region: var("VF_AWS_REGION_NAME")?,
+            endpoint: var("VF_AWS_API_ENDPOINT")?,
+        };
+        let credentials = Credentials::new(
+            Some(&var("VF_AWS_ACCESS_KEY_ID")?),
+            Some(&var("VF_AWS_SECRET_ACCESS_KEY")?),
+            None,
+            None,
+            None,
+        )?;
+
+        // MinIO only supports path-style addressing.
+        Ok(Self::new(
+            Bucket::new(&var("VF_AWS_BUCKET_NAME")?, region, credentials)?.with_path_style(),
+        ))
+    }
+}
+
+/// Turns unexpected S3 response codes into errors.
+fn check_status(key: &str, status: u16) -> Result<()> {
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "S3 responded with {} for object {}",
+            status,
+            key
+        ))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn put(&self, key: &str, path: &Path, mime: &str) -> Result<()> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let status = self
+            .bucket
+            .put_object_stream_with_content_type(&mut file, key, mime)
+            .await?;
+        check_status(key, status)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.bucket.get_object(key).await?;
+        match response.status_code() {
+            404 => Ok(None),
+            status => {
+                check_status(key, status)?;
+                Ok(Some(response.bytes().to_vec()))
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match self.bucket.delete_object(key).await?.status_code() {
+            404 => Ok(()),
+            status => check_status(key, status),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        match self.bucket.head_object(key).await?.1 {
+            404 => Ok(false),
+            status => check_status(key, status).map(|_| true),
+        }
+    }
+
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<Option<String>> {
+        Ok(Some(self.bucket.presign_get(
+            key,
+            expires_in.as_secs() as u32,
+            None,
+        )?))
+    }
+}
@@ -0,0 +1,49 @@
+use super::{LocalStorage, S3Storage};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Where stored files live. Objects are addressed by a key, usually a blake3 hash.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Stores the file at `path` under `key`, replacing anything already there.
+    async fn put(&self, key: &str, path: &Path, mime: &str) -> Result<()>;
+
+    /// Returns the contents stored under `key`.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Removes the object under `key`. Missing objects are not an error.
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Returns true if an object is stored under `key`.
+    async fn exists(&self, key: &str) -> Result<bool>;
+
+    /// Returns a URL clients may fetch the object from directly.
+    /// Backends which cannot hand out URLs return None and are served through the app.
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<Option<String>>;
+
+    /// Returns the path of the object on this machine, if it has one.
+    /// The webserver will serve these itself to support range requests.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Builds the storage backend named by VF_STORAGE_BACKEND.
+/// `local` (default) uses VF_ATTACHMENT_DIR, `s3` uses the VF_AWS_* variables.
+pub fn from_env() -> Result<Arc<dyn StorageBackend>> {
+    let backend = std::env::var("VF_STORAGE_BACKEND").unwrap_or_else(|_| "local".to_owned());
+
+    match backend.to_lowercase().as_str() {
+        "local" => Ok(Arc::new(LocalStorage::new(
+            std::env::var("VF_ATTACHMENT_DIR").unwrap_or_else(|_| "attachments".to_owned()),
+        ))),
+        "s3" => Ok(Arc::new(S3Storage::new_from_env()?)),
+        other => Err(anyhow::anyhow!(
+            "VF_STORAGE_BACKEND must be `local` or `s3`, got `{}`",
+            other
+        )),
+    }
+}
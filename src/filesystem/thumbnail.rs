@@ -0,0 +1,217 @@
+//! Thumbnail and video poster generation.
+//! The first frame of an image, GIF or WebM is scaled into each of [THUMBNAIL_SIZES] and encoded as WebP.
+
+use super::{ffmpeg, hash_file, tmp_dir, StorageBackend};
+use crate::model::attachment::AttachmentThumbnail;
+use actix_web::web::Data;
+use anyhow::Result;
+use ffmpeg::format::Pixel;
+use ffmpeg::software::scaling;
+use scylla::Session;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Bounding boxes thumbnails are generated for, in pixels.
+pub const THUMBNAIL_SIZES: &[u32] = &[160, 320, 640];
+
+/// Pixel format handed to libwebp. Includes alpha for transparent PNG, GIF and WebP.
+const THUMBNAIL_PIXEL_FORMAT: Pixel = Pixel::YUVA420P;
+
+/// A thumbnail written to disk, not yet stored.
+#[derive(Debug)]
+pub struct Thumbnail {
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub hash: String,
+    pub path: PathBuf,
+}
+
+/// Returns dimensions which fit inside a size*size box, keeping aspect ratio.
+/// Images are never scaled up.
+pub fn fit_dimensions(width: u32, height: u32, size: u32) -> (u32, u32) {
+    if width <= size && height <= size {
+        (width, height)
+    } else if width >= height {
+        (size, std::cmp::max(1, height * size / width))
+    } else {
+        (std::cmp::max(1, width * size / height), size)
+    }
+}
+
+/// Decodes the first frame of the best video stream. For videos, this is the poster.
+fn decode_first_frame(path: &Path) -> Result<ffmpeg::frame::Video> {
+    let mut input = ffmpeg::format::input(&path.to_owned())?;
+    let (index, parameters) = {
+        let stream = input
+            .streams()
+            .best(ffmpeg::media::Type::Video)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+        (stream.index(), stream.parameters())
+    };
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(parameters)?
+        .decoder()
+        .video()?;
+
+    let mut frame = ffmpeg::frame::Video::empty();
+    for (stream, packet) in input.packets() {
+        if stream.index() != index {
+            continue;
+        }
+
+        decoder.send_packet(&packet)?;
+        if decoder.receive_frame(&mut frame).is_ok() {
+            return Ok(frame);
+        }
+    }
+
+    // Some decoders hold frames back until they are flushed.
+    decoder.send_eof()?;
+    decoder.receive_frame(&mut frame)?;
+    Ok(frame)
+}
+
+/// Scales a frame and writes it as a WebP file.
+fn encode_webp(frame: &ffmpeg::frame::Video, width: u32, height: u32, dest: &Path) -> Result<()> {
+    let codec =
+        ffmpeg::encoder::find(ffmpeg::codec::Id::WEBP).ok_or(ffmpeg::Error::EncoderNotFound)?;
+
+    let mut scaler = scaling::Context::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        THUMBNAIL_PIXEL_FORMAT,
+        width,
+        height,
+        scaling::Flags::BICUBIC,
+    )?;
+    let mut scaled = ffmpeg::frame::Video::empty();
+    scaler.run(frame, &mut scaled)?;
+    scaled.set_pts(Some(0));
+
+    let mut encoder = ffmpeg::codec::context::Context::new().encoder().video()?;
+    encoder.set_width(width);
+    encoder.set_height(height);
+    encoder.set_format(THUMBNAIL_PIXEL_FORMAT);
+    encoder.set_time_base((1, 1));
+    let mut encoder = encoder.open_as(codec)?;
+
+    encoder.send_frame(&scaled)?;
+    encoder.send_eof()?;
+
+    // libwebp emits each frame as a complete RIFF file, so no muxer is needed.
+    let mut packet = ffmpeg::Packet::empty();
+    encoder.receive_packet(&mut packet)?;
+    File::create(dest)?.write_all(packet.data().unwrap_or_default())?;
+
+    Ok(())
+}
+
+/// Writes a thumbnail for each of [THUMBNAIL_SIZES] into `dir`. Slow and blocking!
+pub fn generate(path: &Path, hash: &str, dir: &Path) -> Result<Vec<Thumbnail>> {
+    let frame = decode_first_frame(path)?;
+    let mut thumbnails = Vec::with_capacity(THUMBNAIL_SIZES.len());
+
+    for size in THUMBNAIL_SIZES {
+        let (width, height) = fit_dimensions(frame.width(), frame.height(), *size);
+        let dest = dir.join(format!("{}-thumb-{}.webp", hash, size));
+
+        encode_webp(&frame, width, height, &dest)?;
+        thumbnails.push(Thumbnail {
+            size: *size,
+            width,
+            height,
+            hash: hash_file(&dest)?.to_string(),
+            path: dest,
+        });
+    }
+
+    Ok(thumbnails)
+}
+
+/// Generates, stores and records thumbnails for a stored attachment.
+pub async fn generate_and_store(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    hash: &str,
+) -> Result<()> {
+    // The same file may be uploaded twice at once, so each run has its own scratch directory.
+    // It is removed however the run ends.
+    let dir = tmp_dir().join(format!("thumbnails-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&dir).await?;
+    let result = store_thumbnails(scylla, storage, hash, &dir).await;
+
+    if let Err(err) = tokio::fs::remove_dir_all(&dir).await {
+        log::warn!("Failed to remove {}: {:?}", dir.display(), err);
+    }
+    result
+}
+
+async fn store_thumbnails(
+    scylla: Data<Session>,
+    storage: Data<dyn StorageBackend>,
+    hash: &str,
+    dir: &Path,
+) -> Result<()> {
+    // Remote files must be pulled down for ffmpeg to read them.
+    let source = match storage.local_path(hash) {
+        Some(path) => path,
+        None => {
+            let bytes = storage
+                .get(hash)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Attachment {} is not in storage", hash))?;
+            let path = dir.join(hash);
+            tokio::fs::write(&path, bytes).await?;
+            path
+        }
+    };
+
+    let thumbnails = {
+        let hash = hash.to_owned();
+        let dir = dir.to_owned();
+        tokio::task::spawn_blocking(move || generate(&source, &hash, &dir)).await??
+    };
+
+    for thumbnail in thumbnails {
+        storage
+            .put(&thumbnail.hash, &thumbnail.path, "image/webp")
+            .await?;
+        AttachmentThumbnail {
+            attachment_hash: hash.to_owned(),
+            size: thumbnail.size as i32,
+            thumbnail_hash: thumbnail.hash.to_owned(),
+            dimension: (thumbnail.width as i32, thumbnail.height as i32),
+        }
+        .insert(scylla.clone())
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Generates thumbnails in the background. Failures are logged; the original is served instead.
+pub fn spawn(scylla: Data<Session>, storage: Data<dyn StorageBackend>, hash: String) {
+    tokio::spawn(async move {
+        if let Err(err) = generate_and_store(scylla, storage, &hash).await {
+            log::warn!("Unable to generate thumbnails for {}: {:?}", hash, err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fit_dimensions;
+
+    #[test]
+    fn test_fit_dimensions() {
+        assert_eq!(fit_dimensions(1920, 1080, 320), (320, 180));
+        assert_eq!(fit_dimensions(1080, 1920, 320), (180, 320));
+        assert_eq!(fit_dimensions(500, 500, 320), (320, 320));
+        // Never upscaled.
+        assert_eq!(fit_dimensions(100, 50, 320), (100, 50));
+        // Never collapses to nothing.
+        assert_eq!(fit_dimensions(10000, 1, 320), (320, 1));
+    }
+}
@@ -0,0 +1,152 @@
+//! Upload validation.
+//! Files must be a type we accept, be demuxed by the matching ffmpeg demuxer,
+//! contain only allowed codecs, and stay within size limits.
+
+use super::{ffmpeg, Error};
+use ffmpeg::codec::Id;
+use ffmpeg::media::Type;
+use std::path::Path;
+
+/// Longest video we accept.
+pub const MAX_DURATION_SECS: i64 = 5 * 60;
+/// Largest image width or height we accept.
+pub const MAX_IMAGE_DIMENSION: u32 = 8192;
+/// Largest video width or height we accept.
+pub const MAX_VIDEO_DIMENSION: u32 = 3840;
+/// Highest overall video bitrate we accept, in bits per second.
+pub const MAX_BITRATE: i64 = 12_000_000;
+
+/// ffmpeg reports container durations in microseconds.
+const AV_TIME_BASE: i64 = 1_000_000;
+
+/// A file type we accept.
+pub struct AllowedType {
+    pub mime: &'static str,
+    /// Demuxer names depend on the file extension, which temporary files do not have.
+    pub demuxers: &'static [&'static str],
+    pub video_codecs: &'static [Id],
+    pub audio_codecs: &'static [Id],
+    pub is_video: bool,
+}
+
+/// Every type we accept. Codecs for WebM are those permitted by the WebM spec.
+pub const ALLOWED_TYPES: &[AllowedType] = &[
+    AllowedType {
+        mime: "image/gif",
+        demuxers: &["gif"],
+        video_codecs: &[Id::GIF],
+        audio_codecs: &[],
+        is_video: false,
+    },
+    AllowedType {
+        mime: "image/jpeg",
+        demuxers: &["image2", "jpeg_pipe"],
+        video_codecs: &[Id::MJPEG],
+        audio_codecs: &[],
+        is_video: false,
+    },
+    AllowedType {
+        mime: "image/png",
+        demuxers: &["image2", "png_pipe"],
+        video_codecs: &[Id::PNG],
+        audio_codecs: &[],
+        is_video: false,
+    },
+    AllowedType {
+        mime: "image/webp",
+        demuxers: &["image2", "webp_pipe"],
+        video_codecs: &[Id::WEBP],
+        audio_codecs: &[],
+        is_video: false,
+    },
+    AllowedType {
+        mime: "video/webm",
+        demuxers: &["matroska,webm"],
+        video_codecs: &[Id::VP8, Id::VP9, Id::AV1],
+        audio_codecs: &[Id::OPUS, Id::VORBIS],
+        is_video: true,
+    },
+];
+
+/// Returns the rules for a mime type, if we accept it at all.
+pub fn get_allowed_type(mime: &str) -> Option<&'static AllowedType> {
+    ALLOWED_TYPES.iter().find(|allowed| allowed.mime == mime)
+}
+
+/// Confirms ffmpeg reads the file as the type we detected and that it is within limits.
+pub fn validate(path: &Path, mime: &str, filesize: u64) -> Result<(), Error> {
+    let allowed = get_allowed_type(mime).ok_or_else(|| Error::UnsupportedType(mime.to_owned()))?;
+    let invalid = |err: ffmpeg::Error| Error::InvalidMedia(err.to_string());
+
+    let input = ffmpeg::format::input(&path.to_owned()).map_err(invalid)?;
+
+    let format_name = input.format().name().to_owned();
+    if !allowed.demuxers.contains(&format_name.as_str()) {
+        return Err(Error::InvalidMedia(format!(
+            "expected {} but found {}",
+            mime, format_name
+        )));
+    }
+
+    let max_dimension = match allowed.is_video {
+        true => MAX_VIDEO_DIMENSION,
+        false => MAX_IMAGE_DIMENSION,
+    };
+    let mut has_video = false;
+
+    for stream in input.streams() {
+        let parameters = stream.parameters();
+        let codec = parameters.id();
+
+        match parameters.medium() {
+            Type::Video if allowed.video_codecs.contains(&codec) => {
+                let decoder = ffmpeg::codec::context::Context::from_parameters(parameters)
+                    .and_then(|context| context.decoder().video())
+                    .map_err(invalid)?;
+                if decoder.width() > max_dimension || decoder.height() > max_dimension {
+                    return Err(Error::InvalidMedia(format!(
+                        "larger than {}x{} pixels",
+                        max_dimension, max_dimension
+                    )));
+                }
+                has_video = true;
+            }
+            Type::Audio if allowed.audio_codecs.contains(&codec) => {}
+            // Subtitles, data and attachment streams are never allowed.
+            medium => {
+                return Err(Error::InvalidMedia(format!(
+                    "{:?} stream with codec {:?} is not allowed",
+                    medium, codec
+                )))
+            }
+        }
+    }
+
+    if !has_video {
+        return Err(Error::InvalidMedia("no image or video stream".to_owned()));
+    }
+
+    if allowed.is_video {
+        // Unknown durations are negative. Those are enforced while remuxing instead.
+        let duration = input.duration();
+        if duration > MAX_DURATION_SECS * AV_TIME_BASE {
+            return Err(Error::InvalidMedia(format!(
+                "longer than {} seconds",
+                MAX_DURATION_SECS
+            )));
+        }
+
+        let bitrate = match input.bit_rate() {
+            0 if duration > 0 => filesize as i64 * 8 * AV_TIME_BASE / duration,
+            bitrate => bitrate,
+        };
+        if bitrate > MAX_BITRATE {
+            return Err(Error::InvalidMedia(format!(
+                "bitrate is above {} kbps",
+                MAX_BITRATE / 1000
+            )));
+        }
+    }
+
+    Ok(())
+}
@@ -11,14 +11,20 @@
 
 extern crate log;
 
+mod account_deletion;
+mod bbcode;
 mod controller;
 mod error;
+mod export;
 mod filesystem;
 mod filters;
 mod middleware;
 mod model;
+mod perm;
+mod reconcile;
 mod session;
 mod util;
+mod views;
 
 #[cfg(test)]
 mod test;
@@ -55,6 +61,33 @@ async fn main() -> std::io::Result<()> {
             .expect("Unable to connect to ScyllaDB"),
     );
 
+    // `volksforo reconcile` corrects counters once and exits.
+    if env::args().nth(1).as_deref() == Some("reconcile") {
+        reconcile::run(scylla)
+            .await
+            .expect("Counter reconciliation failed");
+        return Ok(());
+    }
+
+    // Counters can also be reconciled in the background, every VF_RECONCILE_INTERVAL seconds.
+    match env::var("VF_RECONCILE_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        Some(secs) if secs > 0 => {
+            log::info!("Reconciling counters every {} seconds.", secs);
+            reconcile::spawn(scylla.clone(), std::time::Duration::from_secs(secs));
+        }
+        _ => {}
+    }
+
+    log::info!("Loading permissions.");
+    let permissions = Data::new(
+        perm::new(scylla.clone())
+            .await
+            .expect("Unable to load permission data"),
+    );
+
     // Snowflake ID generator
     // The two env accepted must be unique in a federated cluster.
     // https://en.wikipedia.org/wiki/Snowflake_ID
@@ -71,13 +104,20 @@ async fn main() -> std::io::Result<()> {
         .expect("SNOWFLAKE_BUCKET failed to generate hexafreeze."),
     );
 
+    log::info!("Initializing ffmpeg.");
+    filesystem::init();
+
+    log::info!("Building attachment storage.");
+    let storage: Data<dyn filesystem::StorageBackend> =
+        Data::from(filesystem::from_env().expect("Unable to build attachment storage"));
+
     log::info!("Building Argon2 hash config.");
     util::ARGON2_CONFIG
         .set(argon2::Config {
-            variant: argon2::Variant::Argon2i,
+            variant: argon2::Variant::Argon2id,
             version: argon2::Version::Version13,
-            mem_cost: 4096,
-            time_cost: 3,
+            mem_cost: 19456,
+            time_cost: 2,
             lanes: 1,
             thread_mode: argon2::ThreadMode::Sequential,
             secret: &[],
@@ -86,6 +126,35 @@ async fn main() -> std::io::Result<()> {
         })
         .expect("ARGON2_CONFIG could not be set");
 
+    // Thread views are collected in memory and written every VF_VIEW_FLUSH_INTERVAL seconds.
+    log::info!("Starting view counter.");
+    let views = Data::new(views::ViewCounter::default());
+    views::spawn(
+        views.clone(),
+        scylla.clone(),
+        std::time::Duration::from_secs(
+            env::var("VF_VIEW_FLUSH_INTERVAL")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .unwrap_or(10),
+        ),
+    );
+
+    // Account deletions interrupted by a restart pick up where they left off.
+    log::info!("Resuming account deletions.");
+    account_deletion::resume(scylla.clone(), storage.clone())
+        .await
+        .expect("Unable to resume account deletions");
+
+    // Expired data exports, and those abandoned by a restart, are removed now and then hourly.
+    log::info!("Starting export cleanup.");
+    export::spawn_cleanup(
+        scylla.clone(),
+        storage.clone(),
+        std::time::Duration::from_secs(60 * 60),
+    );
+
     log::info!("Validating session key.");
     let secret_key = match std::env::var("VF_SESSION_KEY") {
         Ok(key) => Key::from(key.as_bytes()),
@@ -101,9 +170,14 @@ async fn main() -> std::io::Result<()> {
     };
 
     // Start webserver
-    HttpServer::new(move || {
+    let server_views = views.clone();
+    let server_scylla = scylla.clone();
+    let result = HttpServer::new(move || {
         App::new()
-            .app_data(scylla.clone())
+            .app_data(server_scylla.clone())
+            .app_data(server_views.clone())
+            .app_data(permissions.clone())
+            .app_data(storage.clone())
             .wrap(Context::default())
             .wrap(SessionMiddleware::new(
                 CookieSessionStore::default(),
@@ -128,5 +202,13 @@ async fn main() -> std::io::Result<()> {
     })
     .bind(env::var("VF_APP_BIND").expect("VF_APP_BIND is unset"))?
     .run()
-    .await
+    .await;
+
+    // Views collected since the last flush would otherwise be lost.
+    log::info!("Flushing {} pending thread views.", views.pending());
+    if let Err(err) = views.flush(scylla).await {
+        log::error!("Flushing thread views failed: {:?}", err);
+    }
+
+    result
 }
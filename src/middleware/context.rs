@@ -1,7 +1,9 @@
-use super::FlashJar;
-use crate::model::UserSession;
+use super::{csrf, FlashJar};
+use crate::model::{Group, Post, UserSession};
+use crate::perm::PermissionData;
 use crate::session::Visitor;
-use actix_web::cookie::Cookie;
+use actix_web::body::EitherBody;
+use actix_web::cookie::{Cookie, SameSite};
 use actix_web::dev::{
     self, Extensions, Payload, Service, ServiceRequest, ServiceResponse, Transform,
 };
@@ -13,9 +15,14 @@ use std::rc::Rc;
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+/// Marks a request which ended the visitor's session, so the middleware does not bump it.
+pub struct SessionEnded;
+
 /// Client context passed to routes.
 #[derive(Debug)]
 pub struct Context {
+    /// Token forms must submit to prove they came from us. See [csrf].
+    pub csrf_token: String,
     /// List of user group ids. Guests may receive unregistered/portal roles.
     pub groups: Vec<i32>,
     /// Flash messages.
@@ -23,7 +30,7 @@ pub struct Context {
     /// Randomly generated string for CSR.
     pub nonce: String,
     /// Permission data.
-    //pub permissions: Data<PermissionData>,
+    pub permissions: Data<PermissionData>,
     /// Time the request started for page load statistics.
     pub request_start: Instant,
     /// Visitor data.
@@ -34,11 +41,12 @@ impl Default for Context {
     fn default() -> Self {
         Self {
             // Guests and users.
-            //permissions: Data::new(PermissionData::default()),
-            groups: Vec::new(),
+            permissions: Data::new(PermissionData::default()),
+            groups: Group::guest_ids(),
             // Only users.
             visitor: Default::default(),
             // Generally left default.
+            csrf_token: Default::default(),
             jar: Default::default(),
             nonce: Self::nonce(),
             request_start: Instant::now(),
@@ -49,12 +57,24 @@ impl Default for Context {
 impl Context {
     /// Pass a Cookie to try and restore a session.
     pub async fn from_cookie(scylla: Data<ScyllaSession>, cookie: &Cookie<'_>) -> Self {
-        //let groups = get_group_ids_for_client(db, &client).await;
         match Uuid::parse_str(cookie.value()) {
-            Ok(uuid) => match Visitor::new_from_uuid(scylla, uuid).await {
-                Ok(visitor) => {
+            Ok(uuid) => match Visitor::new_from_uuid(scylla.clone(), uuid).await {
+                Ok((visitor, _))
+                    if visitor
+                        .session
+                        .as_ref()
+                        .is_some_and(UserSession::is_expired) =>
+                {
+                    log::debug!("Context::from_cookie session expired: {}", uuid);
+                    if let Err(e) = UserSession::delete(scylla, &uuid).await {
+                        log::error!("Failed to delete expired session: {}", e);
+                    }
+                    Self::default()
+                }
+                Ok((visitor, groups)) => {
                     log::debug!("Context::from_cookie visitor: {:?}", &visitor);
                     Self {
+                        groups,
                         visitor,
                         ..Default::default()
                     }
@@ -81,6 +101,41 @@ impl Context {
         }
     }
 
+    /// Returns true if the visitor has been granted a permission.
+    pub fn can(&self, permission: &str) -> bool {
+        self.permissions.can(self, permission)
+    }
+
+    /// Returns true if the visitor has been granted a permission in a resource (i.e. a forum).
+    pub fn can_in(&self, permission: &str, resource_id: i64) -> bool {
+        self.permissions.can_in(self, permission, resource_id)
+    }
+
+    /// Returns true if the visitor wrote this post.
+    pub fn is_author(&self, post: &Post) -> bool {
+        match (&self.visitor.user, post.user_id) {
+            (Some(user), Some(author)) => user.id == author,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the visitor may edit a post in a forum.
+    pub fn can_edit_post(&self, post: &Post, node_id: i64) -> bool {
+        self.can_in("post.edit_any", node_id)
+            || (self.is_author(post) && self.can_in("post.edit_own", node_id))
+    }
+
+    /// Returns true if the visitor may delete a post in a forum.
+    pub fn can_delete_post(&self, post: &Post, node_id: i64) -> bool {
+        self.can_in("post.delete_any", node_id)
+            || (self.is_author(post) && self.can_in("post.delete_own", node_id))
+    }
+
+    /// Returns true if the visitor may read a post's earlier revisions.
+    pub fn can_view_post_history(&self, post: &Post, node_id: i64) -> bool {
+        self.can_in("post.view_history", node_id) || self.is_author(post)
+    }
+
     /// Returns a hash unique to each request used for CSP.
     /// See: <https://developer.mozilla.org/en-US/docs/Web/HTML/Global_attributes/nonce>
     /// and <https://developer.mozilla.org/en-US/docs/Web/HTTP/CSP>
@@ -107,6 +162,14 @@ impl Context {
         &self.nonce
     }
 
+    /// Binds the CSRF token to the visitor's session, or to the guest cookie if they have none.
+    pub fn bind_csrf_token(&mut self, guest_secret: &str) {
+        self.csrf_token = match &self.visitor.session {
+            Some(session) => csrf::token(&format!("session:{}", session.id)),
+            None => csrf::token(&format!("guest:{}", guest_secret)),
+        };
+    }
+
     /// Returns Duration representing request time.
     pub fn request_time(&self) -> Duration {
         Instant::now() - self.request_start
@@ -142,7 +205,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = ContextMiddleware<S>;
@@ -166,7 +229,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -179,32 +242,91 @@ where
         let (httpreq, payload) = req.into_parts();
         //let session = ActixSession::extract(&httpreq).into_inner();
         let scylla = httpreq.app_data::<Data<ScyllaSession>>().cloned(); // Clone like this to avoid inheritence issues with next line.
+        let permissions = httpreq.app_data::<Data<PermissionData>>().cloned();
         let cookie = httpreq.cookie("vf_session");
-        let req = ServiceRequest::from_parts(httpreq, payload);
+        let guest_secret = httpreq
+            .cookie(csrf::GUEST_COOKIE)
+            .map(|cookie| cookie.value().to_owned());
+        let mut req = ServiceRequest::from_parts(httpreq, payload);
 
         // If we do not have permission data there is no client interface to access.
         Box::pin(async move {
-            if let Some(cookie) = &cookie {
-                if let Some(scylla) = scylla {
+            let mut bump = None;
+            let mut context = match (&cookie, scylla) {
+                (Some(cookie), Some(scylla)) => {
                     let context = Context::from_cookie(scylla.clone(), cookie).await;
+                    bump = context
+                        .visitor
+                        .session
+                        .to_owned()
+                        .filter(UserSession::needs_bump)
+                        .map(|session| (scylla, session));
+                    context
+                }
+                _ => Context::default(),
+            };
 
-                    if let Some(session_id) = &context.visitor.session_id {
-                        let uuid = session_id.to_owned();
-                        tokio::spawn(async move {
-                            match UserSession::bump_last_seen_at(scylla.clone(), &uuid).await {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    log::error!("Failed to bump last seen at: {}", err);
-                                }
-                            }
-                        });
+            if let Some(permissions) = permissions {
+                context.permissions = permissions;
+            }
+
+            // Guests are given a cookie to bind their token to on their first request.
+            let new_guest_secret = match &guest_secret {
+                Some(_) => None,
+                None => Some(csrf::new_guest_secret()),
+            };
+            context.bind_csrf_token(
+                guest_secret
+                    .as_deref()
+                    .or(new_guest_secret.as_deref())
+                    .unwrap_or_default(),
+            );
+
+            // Anything which changes state must come from one of our own pages.
+            let mut res = if req.method().is_safe() {
+                req.extensions_mut().insert(context);
+                svc.call(req).await?.map_into_left_body()
+            } else {
+                match csrf::submitted_token(&mut req).await? {
+                    Some(token) if csrf::verify(&context.csrf_token, &token) => {
+                        req.extensions_mut().insert(context);
+                        svc.call(req).await?.map_into_left_body()
                     }
+                    _ => {
+                        log::debug!(
+                            "Rejected request without a valid CSRF token: {}",
+                            req.path()
+                        );
+                        let res = crate::controller::error::csrf_document(context);
+                        req.into_response(res).map_into_right_body()
+                    }
+                }
+            };
 
-                    req.extensions_mut().insert(context);
+            // Sessions are bumped now and then once the route is done with them, unless it ended them.
+            if let Some((scylla, session)) = bump {
+                if res.request().extensions().get::<SessionEnded>().is_none() {
+                    tokio::spawn(async move {
+                        match session.bump_last_seen_at(scylla).await {
+                            Ok(_) => {}
+                            Err(err) => {
+                                log::error!("Failed to bump last seen at: {}", err);
+                            }
+                        }
+                    });
                 }
             }
 
-            svc.call(req).await
+            if let Some(secret) = new_guest_secret {
+                let guest_cookie = Cookie::build(csrf::GUEST_COOKIE, secret)
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .finish();
+                res.response_mut().add_cookie(&guest_cookie)?;
+            }
+
+            Ok(res)
         })
     }
 }
@@ -0,0 +1,213 @@
+//! Cross-site request forgery protection.
+//! Every page carries a token bound to the visitor's session, or for guests to a random cookie.
+//! Forms submit it as their first field and scripts send it in a header. [super::ContextMiddleware]
+//! rejects unsafe requests without it. Only the start of a body is read, so uploads still stream.
+
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures_util::{stream, StreamExt};
+use once_cell::sync::Lazy;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+/// Cookie binding tokens for visitors without a session.
+pub const GUEST_COOKIE: &str = "vf_csrf";
+
+/// Form field carrying the token. It must be the first field in the form.
+pub const FIELD: &str = "csrf_token";
+
+/// Header carrying the token for requests made by scripts.
+pub const HEADER: &str = "X-CSRF-Token";
+
+/// Most bytes read looking for the token before giving up.
+const PREFIX_LIMIT: usize = 4096;
+
+static KEY: Lazy<[u8; 32]> = Lazy::new(|| {
+    blake3::derive_key(
+        "volksforo 2023-05 csrf token",
+        std::env::var("VF_SALT")
+            .expect("VF_SALT is unset")
+            .as_bytes(),
+    )
+});
+
+/// Returns a new random value for the guest cookie.
+pub fn new_guest_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Returns the token for a binding, such as a session id.
+pub fn token(binding: &str) -> String {
+    blake3::keyed_hash(&KEY, binding.as_bytes())
+        .to_hex()
+        .to_string()
+}
+
+/// Compares tokens in constant time.
+pub fn verify(expected: &str, submitted: &str) -> bool {
+    match (
+        blake3::Hash::from_hex(expected),
+        blake3::Hash::from_hex(submitted),
+    ) {
+        (Ok(expected), Ok(submitted)) => expected == submitted,
+        _ => false,
+    }
+}
+
+/// Outcome of looking for the token at the start of a body.
+#[derive(Debug, PartialEq)]
+enum Scan {
+    Found(String),
+    Missing,
+    Incomplete,
+}
+
+/// Returns the token submitted with a request, if any.
+/// Body bytes read while looking for it are put back for the route to read.
+pub async fn submitted_token(req: &mut ServiceRequest) -> Result<Option<String>, Error> {
+    if let Some(token) = req
+        .headers()
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        return Ok(Some(token.to_owned()));
+    }
+
+    let boundary = match req.mime_type()? {
+        Some(mime) if mime == mime::APPLICATION_WWW_FORM_URLENCODED => None,
+        Some(mime) if mime.essence_str() == mime::MULTIPART_FORM_DATA.essence_str() => {
+            match mime.get_param(mime::BOUNDARY) {
+                Some(boundary) => Some(boundary.as_str().to_owned()),
+                None => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    let mut payload = req.take_payload();
+    let mut prefix = BytesMut::new();
+    let token = loop {
+        let eof = match payload.next().await {
+            Some(chunk) => {
+                prefix.extend_from_slice(&chunk?);
+                false
+            }
+            None => true,
+        };
+
+        let scan = match &boundary {
+            Some(boundary) => scan_multipart(&prefix, boundary),
+            None => scan_urlencoded(&prefix, eof),
+        };
+        match scan {
+            Scan::Found(token) => break Some(token),
+            Scan::Missing => break None,
+            Scan::Incomplete if eof || prefix.len() >= PREFIX_LIMIT => break None,
+            Scan::Incomplete => {}
+        }
+    };
+
+    let prefix: Bytes = prefix.freeze();
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(stream::once(async move { Ok(prefix) }).chain(payload)),
+    });
+
+    Ok(token)
+}
+
+/// Reads the token from the first field of a `application/x-www-form-urlencoded` body.
+fn scan_urlencoded(body: &[u8], eof: bool) -> Scan {
+    let end = match body.iter().position(|b| *b == b'&') {
+        Some(end) => end,
+        None if eof => body.len(),
+        None => {
+            // The field name alone is enough to tell if the token was left out.
+            return match body.iter().position(|b| *b == b'=') {
+                Some(eq) if &body[..eq] != FIELD.as_bytes() => Scan::Missing,
+                _ => Scan::Incomplete,
+            };
+        }
+    };
+
+    match body[..end].split(|b| *b == b'=').collect::<Vec<_>>()[..] {
+        [name, value] if name == FIELD.as_bytes() => {
+            Scan::Found(String::from_utf8_lossy(value).into_owned())
+        }
+        _ => Scan::Missing,
+    }
+}
+
+/// Reads the token from the first part of a `multipart/form-data` body.
+fn scan_multipart(body: &[u8], boundary: &str) -> Scan {
+    let delimiter = format!("--{}\r\n", boundary);
+    if body.len() < delimiter.len() {
+        return Scan::Incomplete;
+    }
+    if !body.starts_with(delimiter.as_bytes()) {
+        return Scan::Missing;
+    }
+
+    let part = &body[delimiter.len()..];
+    let headers_end = match find(part, b"\r\n\r\n") {
+        Some(end) => end,
+        None => return Scan::Incomplete,
+    };
+    let name = format!("name=\"{}\"", FIELD);
+    if find(&part[..headers_end], name.as_bytes()).is_none() {
+        return Scan::Missing;
+    }
+
+    let value = &part[headers_end + 4..];
+    match find(value, format!("\r\n--{}", boundary).as_bytes()) {
+        Some(end) => Scan::Found(String::from_utf8_lossy(&value[..end]).into_owned()),
+        None => Scan::Incomplete,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scan_multipart, scan_urlencoded, Scan};
+
+    #[test]
+    fn test_scan_urlencoded() {
+        assert_eq!(
+            scan_urlencoded(b"csrf_token=abc&username=x", false),
+            Scan::Found("abc".to_owned())
+        );
+        assert_eq!(
+            scan_urlencoded(b"csrf_token=abc", true),
+            Scan::Found("abc".to_owned())
+        );
+        assert_eq!(scan_urlencoded(b"csrf_token=ab", false), Scan::Incomplete);
+        assert_eq!(
+            scan_urlencoded(b"username=x&csrf_token=abc", true),
+            Scan::Missing
+        );
+        assert_eq!(scan_urlencoded(b"username=x", false), Scan::Missing);
+        assert_eq!(scan_urlencoded(b"", true), Scan::Missing);
+    }
+
+    #[test]
+    fn test_scan_multipart() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\nabc\r\n--xyz\r\nContent-Disposition: form-data; name=\"content\"\r\n\r\nhi\r\n--xyz--\r\n";
+        assert_eq!(scan_multipart(body, "xyz"), Scan::Found("abc".to_owned()));
+        assert_eq!(scan_multipart(&body[..60], "xyz"), Scan::Incomplete);
+        assert_eq!(scan_multipart(&body[..3], "xyz"), Scan::Incomplete);
+
+        let body =
+            b"--xyz\r\nContent-Disposition: form-data; name=\"content\"\r\n\r\nhi\r\n--xyz--\r\n";
+        assert_eq!(scan_multipart(body, "xyz"), Scan::Missing);
+        assert_eq!(scan_multipart(b"--abc\r\n", "xyz"), Scan::Missing);
+    }
+}
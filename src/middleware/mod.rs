@@ -5,6 +5,8 @@
 
 pub mod context;
 pub use context::Context;
+pub use context::SessionEnded;
+pub mod csrf;
 pub mod flash;
 pub use flash::Flash;
 pub use flash::FlashJar;
@@ -1 +1,346 @@
+use super::Post;
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::Duration;
+use scylla::{cql_to_rust::FromRowError, frame::value, FromRow, IntoTypedRows, Session};
+use std::collections::HashMap;
+use tokio::task::JoinSet;
 
+/// A unique file, addressed by the blake3 hash of its contents.
+#[derive(Debug, FromRow, Clone)]
+pub struct Attachment {
+    pub hash: String,
+    pub first_seen_at: Duration,
+    pub last_seen_at: Duration,
+    pub filesize: i64,
+    pub mime: String,
+}
+
+/// Relationship between a post and a file it carries.
+#[derive(Debug, FromRow, Clone)]
+pub struct PostAttachment {
+    pub post_id: i64,
+    pub attachment_hash: String,
+    pub filename: String,
+}
+
+/// A scaled WebP copy of an attachment, stored under its own hash.
+#[derive(Debug, FromRow, Clone)]
+pub struct AttachmentThumbnail {
+    pub attachment_hash: String,
+    pub size: i32,
+    pub thumbnail_hash: String,
+    pub dimension: (i32, i32),
+}
+
+impl AttachmentThumbnail {
+    pub async fn fetch(scylla: Data<Session>, hash: &str, size: i32) -> Result<Option<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT
+                    attachment_hash,
+                    size,
+                    thumbnail_hash,
+                    dimension
+                FROM volksforo.attachment_thumbnails
+                WHERE attachment_hash = ? AND size = ?
+                ;"#,
+                (hash, size),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?
+            .pop())
+    }
+
+    pub async fn insert(&self, scylla: Data<Session>) -> Result<()> {
+        scylla
+            .query(
+                r#"INSERT INTO volksforo.attachment_thumbnails (
+                    attachment_hash,
+                    size,
+                    thumbnail_hash,
+                    dimension
+                )
+                VALUES (?, ?, ?, ?)
+                ;"#,
+                (
+                    &self.attachment_hash,
+                    self.size,
+                    &self.thumbnail_hash,
+                    self.dimension,
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Attachment {
+    pub async fn fetch(scylla: Data<Session>, hash: &str) -> Result<Option<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT
+                    hash,
+                    first_seen_at,
+                    last_seen_at,
+                    filesize,
+                    mime
+                FROM volksforo.attachments
+                WHERE hash = ?
+                ;"#,
+                (hash,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?
+            .pop())
+    }
+
+    /// Returns the stored file uploaded as a file with this hash.
+    /// Files stored unchanged are found by their own hash, and stripped ones through `attachment_sources`.
+    pub async fn fetch_by_source(scylla: Data<Session>, source_hash: &str) -> Result<Option<Self>> {
+        let stored_hash = scylla
+            .query(
+                "SELECT attachment_hash FROM volksforo.attachment_sources WHERE source_hash = ?;",
+                (source_hash,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(String,)>()
+            .collect::<Result<Vec<(String,)>, FromRowError>>()?
+            .pop()
+            .map(|row| row.0);
+
+        Self::fetch(scylla, stored_hash.as_deref().unwrap_or(source_hash)).await
+    }
+
+    /// Records that a file uploaded with `source_hash` was stored as `hash`.
+    pub async fn insert_source(scylla: Data<Session>, source_hash: &str, hash: &str) -> Result<()> {
+        scylla
+            .query(
+                "INSERT INTO volksforo.attachment_sources (source_hash, attachment_hash) VALUES (?, ?);",
+                (source_hash, hash),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records an upload of a file.
+    /// New files are inserted. Files we have seen before have their last_seen_at bumped.
+    pub async fn insert_or_touch(
+        scylla: Data<Session>,
+        hash: &str,
+        filesize: i64,
+        mime: &str,
+    ) -> Result<Self> {
+        let timestamp = chrono::Utc::now().timestamp_millis();
+
+        let result = scylla
+            .query(
+                r#"INSERT INTO volksforo.attachments (
+                    hash,
+                    first_seen_at,
+                    last_seen_at,
+                    filesize,
+                    mime
+                )
+                VALUES (?, ?, ?, ?, ?)
+                IF NOT EXISTS
+                ;"#,
+                (hash, timestamp, timestamp, filesize, mime),
+            )
+            .await?;
+
+        // Seen before; the first_seen_at is kept.
+        if !crate::util::is_applied(result) {
+            scylla
+                .query(
+                    "UPDATE volksforo.attachments SET last_seen_at = ? WHERE hash = ?;",
+                    (timestamp, hash),
+                )
+                .await?;
+        }
+
+        match Self::fetch(scylla, hash).await? {
+            Some(attachment) => Ok(attachment),
+            None => Err(anyhow::Error::new(crate::Error::Infallible(
+                "Infallible select of attachment row we just inserted somehow returned no results.",
+            ))),
+        }
+    }
+
+    /// Links a file to a post. A file attached twice to the same post is only linked once.
+    pub async fn attach_to_post(
+        scylla: Data<Session>,
+        post_id: i64,
+        hash: &str,
+        filename: &str,
+    ) -> Result<()> {
+        let result = scylla
+            .query(
+                r#"INSERT INTO volksforo.post_attachments (
+                    post_id,
+                    attachment_hash,
+                    filename
+                )
+                VALUES (?, ?, ?)
+                IF NOT EXISTS
+                ;"#,
+                (post_id, hash, filename),
+            )
+            .await?;
+
+        if crate::util::is_applied(result) {
+            Self::increment_reference_count(scylla, hash, 1).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Unlinks every file from a post.
+    pub async fn detach_all_from_post(scylla: Data<Session>, post_id: i64) -> Result<()> {
+        let hashes = scylla
+            .query(
+                "SELECT attachment_hash FROM volksforo.post_attachments WHERE post_id = ?",
+                (post_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(String,)>()
+            .map(|r| r.map(|r| r.0))
+            .collect::<Result<Vec<String>, FromRowError>>()?;
+
+        scylla
+            .query(
+                "DELETE FROM volksforo.post_attachments WHERE post_id = ?",
+                (post_id,),
+            )
+            .await?;
+
+        for hash in hashes {
+            Self::increment_reference_count(scylla.clone(), &hash, -1).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Adjusts how many posts a file is attached to. Counters can only be incremented or decremented.
+    pub async fn increment_reference_count(
+        scylla: Data<Session>,
+        hash: &str,
+        amount: i64,
+    ) -> Result<()> {
+        scylla
+            .query(
+                "UPDATE volksforo.attachment_references SET reference_count = reference_count + ? WHERE hash = ?",
+                (value::Counter(amount), hash),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Fetches how many posts a file is attached to, by its counter.
+    pub async fn fetch_reference_count(scylla: Data<Session>, hash: &str) -> Result<Option<i64>> {
+        Ok(scylla
+            .query(
+                "SELECT hash, reference_count FROM volksforo.attachment_references WHERE hash = ?",
+                (hash,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(String, value::Counter)>()
+            .collect::<Result<Vec<(String, value::Counter)>, FromRowError>>()?
+            .pop()
+            .map(|r| r.1 .0))
+    }
+
+    /// Counts the posts a file is attached to.
+    pub async fn count_posts(scylla: Data<Session>, hash: &str) -> Result<i64> {
+        Ok(scylla
+            .query(
+                "SELECT COUNT(*) FROM volksforo.post_attachments WHERE attachment_hash = ?",
+                (hash,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(i64,)>()
+            .collect::<Result<Vec<(i64,)>, FromRowError>>()?
+            .pop()
+            .map(|r| r.0)
+            .unwrap_or(0))
+    }
+
+    /// Corrects a file's reference count to the number of posts it is attached to.
+    /// Returns the correction applied, which is 0 if the count was right.
+    pub async fn sync_reference_count(scylla: Data<Session>, hash: &str) -> Result<i64> {
+        let (expected, current) = match tokio::join!(
+            Self::count_posts(scylla.clone(), hash),
+            Self::fetch_reference_count(scylla.clone(), hash),
+        ) {
+            (Ok(expected), Ok(current)) => (expected, current.unwrap_or(0)),
+            (Err(err), _) => return Err(err),
+            (_, Err(err)) => return Err(err),
+        };
+
+        let correction = expected - current;
+        if correction != 0 {
+            Self::increment_reference_count(scylla, hash, correction).await?;
+        }
+
+        Ok(correction)
+    }
+
+    /// Returns a map of post ids to their attachments.
+    pub async fn fetch_many_posts(
+        scylla: Data<Session>,
+        posts: &[Post],
+    ) -> Result<HashMap<i64, Vec<PostAttachment>>> {
+        let mut queries = JoinSet::new();
+        let mut attachments = HashMap::with_capacity(posts.len());
+
+        for post in posts {
+            let nscylla = scylla.to_owned();
+            let post_id = post.id;
+            queries.spawn(async move {
+                nscylla
+                    .query(
+                        r#"SELECT
+                            post_id,
+                            attachment_hash,
+                            filename
+                        FROM volksforo.post_attachments
+                        WHERE post_id = ?
+                        ;"#,
+                        (post_id,),
+                    )
+                    .await
+            });
+        }
+
+        while let Some(result) = queries.join_next().await {
+            if let Some(rows) = result??.rows {
+                for row in rows.into_typed::<PostAttachment>() {
+                    let model = row?;
+                    attachments
+                        .entry(model.post_id)
+                        .or_insert_with(Vec::new)
+                        .push(model);
+                }
+            }
+        }
+
+        Ok(attachments)
+    }
+}
@@ -0,0 +1,158 @@
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::Duration;
+use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
+use std::collections::HashMap;
+use tokio::task::JoinSet;
+
+/// What kind of content a deletion applies to. Each kind has its own table.
+#[derive(Clone, Copy, Debug)]
+pub enum DeletionKind {
+    Post,
+    Thread,
+}
+
+impl DeletionKind {
+    fn table(self) -> &'static str {
+        match self {
+            Self::Post => "volksforo.post_deletions",
+            Self::Thread => "volksforo.thread_deletions",
+        }
+    }
+}
+
+/// Record of who deleted a post or thread, when, and why.
+/// Soft-deleted content is hidden until the record is removed. Purged content keeps its record.
+#[derive(Debug, FromRow)]
+pub struct Deletion {
+    pub id: i64,
+    pub deleted_by: Option<i64>,
+    pub deleted_at: Duration,
+    pub reason: Option<String>,
+}
+
+impl Deletion {
+    /// Inserts the record unless one exists. Returns true if this call created it.
+    pub async fn insert_if_absent(
+        &self,
+        scylla: Data<Session>,
+        kind: DeletionKind,
+    ) -> Result<bool> {
+        let result = scylla
+            .query(
+                format!(
+                    r#"INSERT INTO {} (
+                        id,
+                        deleted_by,
+                        deleted_at,
+                        reason
+                    )
+                    VALUES (?, ?, ?, ?)
+                    IF NOT EXISTS
+                    ;"#,
+                    kind.table()
+                ),
+                (
+                    self.id,
+                    self.deleted_by,
+                    self.deleted_at.num_milliseconds(),
+                    &self.reason,
+                ),
+            )
+            .await?;
+
+        Ok(crate::util::is_applied(result))
+    }
+
+    /// Inserts the record, replacing any existing one.
+    pub async fn insert(&self, scylla: Data<Session>, kind: DeletionKind) -> Result<()> {
+        scylla
+            .query(
+                format!(
+                    r#"INSERT INTO {} (
+                        id,
+                        deleted_by,
+                        deleted_at,
+                        reason
+                    )
+                    VALUES (?, ?, ?, ?)
+                    ;"#,
+                    kind.table()
+                ),
+                (
+                    self.id,
+                    self.deleted_by,
+                    self.deleted_at.num_milliseconds(),
+                    &self.reason,
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes the record. Returns true if this call removed it.
+    pub async fn remove(scylla: Data<Session>, kind: DeletionKind, id: i64) -> Result<bool> {
+        let result = scylla
+            .query(
+                format!("DELETE FROM {} WHERE id = ? IF EXISTS", kind.table()),
+                (id,),
+            )
+            .await?;
+
+        Ok(crate::util::is_applied(result))
+    }
+
+    pub async fn fetch(scylla: Data<Session>, kind: DeletionKind, id: i64) -> Result<Option<Self>> {
+        Ok(scylla
+            .query(
+                format!(
+                    "SELECT id, deleted_by, deleted_at, reason FROM {} WHERE id = ?",
+                    kind.table()
+                ),
+                (id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?
+            .pop())
+    }
+
+    /// Returns a map of ids to deletion records. Ids which are not deleted are absent.
+    pub async fn fetch_many(
+        scylla: Data<Session>,
+        kind: DeletionKind,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Self>> {
+        let mut queries = JoinSet::new();
+        let mut models = HashMap::new();
+
+        for id in ids {
+            let nscylla = scylla.to_owned();
+            queries.spawn(async move {
+                nscylla
+                    .query(
+                        format!(
+                            "SELECT id, deleted_by, deleted_at, reason FROM {} WHERE id = ?",
+                            kind.table()
+                        ),
+                        (id,),
+                    )
+                    .await
+            });
+        }
+
+        while let Some(result) = queries.join_next().await {
+            if let Some(rows) = result??.rows {
+                for row in rows.into_typed::<Self>() {
+                    let model = row?;
+                    models.insert(model.id, model);
+                }
+            }
+        }
+
+        Ok(models)
+    }
+}
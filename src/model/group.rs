@@ -0,0 +1,77 @@
+use actix_web::web::Data;
+use anyhow::Result;
+use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
+
+/// Group every visitor without a session belongs to.
+pub const GUEST_GROUP_ID: i32 = 1;
+/// Group every signed in user belongs to.
+pub const REGISTERED_GROUP_ID: i32 = 2;
+
+#[derive(Debug, FromRow, Clone)]
+pub struct Group {
+    pub id: i32,
+    pub label: String,
+}
+
+impl Group {
+    /// Adds a user to a group.
+    pub async fn assign_user(scylla: Data<Session>, user_id: i64, group_id: i32) -> Result<()> {
+        scylla
+            .query(
+                "INSERT INTO volksforo.user_groups (user_id, group_id) VALUES (?, ?);",
+                (user_id, group_id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes a user from every group.
+    pub async fn remove_user(scylla: Data<Session>, user_id: i64) -> Result<()> {
+        scylla
+            .query(
+                "DELETE FROM volksforo.user_groups WHERE user_id = ?;",
+                (user_id,),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn fetch_all(scylla: Data<Session>) -> Result<Vec<Self>> {
+        Ok(scylla
+            .query("SELECT id, label FROM volksforo.groups", &[])
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?)
+    }
+
+    /// Returns the group ids a user belongs to, including automatic groups.
+    pub async fn fetch_ids_for_user(scylla: Data<Session>, user_id: i64) -> Result<Vec<i32>> {
+        let mut ids = scylla
+            .query(
+                "SELECT group_id FROM volksforo.user_groups WHERE user_id = ?",
+                (user_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(i32,)>()
+            .map(|row| row.map(|(id,)| id))
+            .collect::<Result<Vec<i32>, FromRowError>>()?;
+
+        // Users imported without a membership row are still registered.
+        if !ids.contains(&REGISTERED_GROUP_ID) {
+            ids.push(REGISTERED_GROUP_ID);
+        }
+
+        Ok(ids)
+    }
+
+    /// Returns the group ids of a visitor without a session.
+    pub fn guest_ids() -> Vec<i32> {
+        vec![GUEST_GROUP_ID]
+    }
+}
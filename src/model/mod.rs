@@ -1,3 +1,9 @@
+pub mod attachment;
+pub use attachment::Attachment;
+pub mod deletion;
+pub use deletion::Deletion;
+pub mod group;
+pub use group::Group;
 pub mod node;
 pub use node::Node;
 pub mod post;
@@ -8,5 +14,11 @@ pub mod ugc;
 pub use ugc::Ugc;
 pub mod user;
 pub use user::User;
+pub mod user_deletion;
+pub use user_deletion::UserDeletion;
+pub mod user_export;
+pub use user_export::UserExport;
+pub mod user_name;
+pub use user_name::UserName;
 pub mod user_session;
 pub use user_session::UserSession;
@@ -1,10 +1,11 @@
 use actix_web::web::Data;
 use anyhow::Result;
-use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
+use scylla::{cql_to_rust::FromRowError, frame::value, FromRow, IntoTypedRows, Session};
 
 #[derive(Debug, FromRow)]
 pub struct Node {
     pub id: i64,
+    pub parent_id: Option<i64>,
     pub display_order: i32,
     pub title: String,
     pub description: Option<String>,
@@ -14,7 +15,7 @@ impl Node {
     pub async fn fetch(scylla: Data<Session>, node_id: i64) -> Result<Option<Self>> {
         Ok(scylla
             .query(
-                "SELECT id, display_order, title, description FROM volksforo.nodes WHERE id = ?",
+                "SELECT id, parent_id, display_order, title, description FROM volksforo.nodes WHERE id = ?",
                 (node_id,),
             )
             .await?
@@ -28,7 +29,7 @@ impl Node {
     pub async fn fetch_all(scylla: Data<Session>) -> Result<Vec<Self>> {
         if let Some(rows) = scylla
             .query(
-                "SELECT id, display_order, title, description FROM volksforo.nodes",
+                "SELECT id, parent_id, display_order, title, description FROM volksforo.nodes",
                 &[],
             )
             .await?
@@ -46,4 +47,36 @@ impl Node {
             Ok(Vec::default())
         }
     }
+
+    /// Adjusts a forum's thread count. Counters can only be incremented or decremented.
+    pub async fn increment_thread_count(
+        scylla: Data<Session>,
+        node_id: i64,
+        amount: i64,
+    ) -> Result<()> {
+        scylla
+            .query(
+                "UPDATE volksforo.node_threads SET thread_count = thread_count + ? WHERE id = ?",
+                (value::Counter(amount), node_id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Fetches the thread count of a single forum.
+    pub async fn fetch_thread_count(scylla: Data<Session>, node_id: i64) -> Result<Option<i64>> {
+        Ok(scylla
+            .query(
+                "SELECT id, thread_count FROM volksforo.node_threads WHERE id = ?",
+                (node_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(i64, value::Counter)>()
+            .collect::<Result<Vec<(i64, value::Counter)>, FromRowError>>()?
+            .pop()
+            .map(|r| r.1 .0))
+    }
 }
@@ -1,6 +1,9 @@
+use super::deletion::{Deletion, DeletionKind};
+use super::{Attachment, Thread, Ugc};
 use actix_web::web::Data;
 use anyhow::Result;
 use chrono::Duration;
+use futures_util::StreamExt;
 use scylla::cql_to_rust::FromRowError;
 use scylla::query::Query;
 use scylla::statement::{Consistency, SerialConsistency};
@@ -9,6 +12,10 @@ use std::collections::HashMap;
 use tokio::task::JoinSet;
 use uuid::Uuid;
 
+/// How many times claiming a position is attempted before giving up.
+/// Every failed attempt means another reply claimed the position first.
+const SEQUENCE_ATTEMPTS: usize = 16;
+
 // Define custom struct that matches User Defined Type created earlier
 // wrapping field in Option will gracefully handle null field values
 #[derive(Debug, FromRow)]
@@ -37,8 +44,7 @@ impl Post {
                     user_id,
                     ugc_id
                 FROM volksforo.posts
-                WHERE
-                    post_id ?
+                WHERE id = ?
                 ;"#,
                 (post_id,),
             )
@@ -50,6 +56,32 @@ impl Post {
             .pop())
     }
 
+    /// Returns every post a user wrote.
+    pub async fn fetch_for_user(scylla: Data<scylla::Session>, user_id: i64) -> Result<Vec<Self>> {
+        let mut rows = scylla
+            .query_iter(
+                r#"SELECT
+                    id,
+                    thread_id,
+                    created_at,
+                    user_id,
+                    ugc_id
+                FROM volksforo.posts
+                WHERE user_id = ?
+                ;"#,
+                (user_id,),
+            )
+            .await?
+            .into_typed::<Self>();
+        let mut posts = Vec::new();
+
+        while let Some(row) = rows.next().await {
+            posts.push(row?);
+        }
+
+        Ok(posts)
+    }
+
     pub async fn fetch_many(
         scylla: Data<scylla::Session>,
         post_ids: Vec<i64>,
@@ -97,19 +129,152 @@ impl Post {
         Ok(posts)
     }
 
+    /// Returns the position of a post in its thread.
+    pub async fn fetch_position(
+        scylla: Data<scylla::Session>,
+        thread_id: i64,
+        post_id: i64,
+    ) -> Result<Option<i64>> {
+        Ok(scylla
+            .query(
+                r#"SELECT position
+                    FROM volksforo.post_positions
+                    WHERE thread_id = ? AND post_id = ?
+                ;"#,
+                (thread_id, post_id),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(i64,)>()
+            .collect::<Result<Vec<(i64,)>, FromRowError>>()?
+            .pop()
+            .map(|r| r.0))
+    }
+
+    /// Returns the highest position in a thread. Deleted posts keep their positions, so this may exceed the reply count.
+    pub async fn fetch_last_position(
+        scylla: Data<scylla::Session>,
+        thread_id: i64,
+    ) -> Result<Option<i64>> {
+        Ok(scylla
+            .query(
+                r#"SELECT position
+                    FROM volksforo.post_positions
+                    WHERE thread_id = ?
+                    ORDER BY position DESC
+                    LIMIT 1
+                ;"#,
+                (thread_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(i64,)>()
+            .collect::<Result<Vec<(i64,)>, FromRowError>>()?
+            .pop()
+            .map(|r| r.0))
+    }
+
+    /// Returns the ids of every post in a thread, in position order.
+    pub async fn fetch_thread_ids(
+        scylla: Data<scylla::Session>,
+        thread_id: i64,
+    ) -> Result<Vec<i64>> {
+        Ok(scylla
+            .query(
+                "SELECT post_id FROM volksforo.post_positions WHERE thread_id = ?",
+                (thread_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(i64,)>()
+            .map(|r| r.map(|r| r.0))
+            .collect::<Result<Vec<i64>, FromRowError>>()?)
+    }
+
+    /// Moves posts to another thread. Positions are not touched; see `write_positions`.
+    pub async fn set_thread(
+        scylla: Data<scylla::Session>,
+        post_ids: &[i64],
+        thread_id: i64,
+    ) -> Result<()> {
+        let mut queries = JoinSet::new();
+
+        for post_id in post_ids.iter().copied() {
+            let nscylla = scylla.to_owned();
+            queries.spawn(async move {
+                nscylla
+                    .query(
+                        "UPDATE volksforo.posts SET thread_id = ? WHERE id = ?",
+                        (thread_id, post_id),
+                    )
+                    .await
+            });
+        }
+
+        while let Some(result) = queries.join_next().await {
+            result??;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces a thread's positions with these posts, numbered from 1 in the order they were made,
+    /// and restarts the thread's sequence after the last of them.
+    /// A reply made while this runs may lose its position. If it is the newest post, `Thread::repair` puts it back.
+    pub async fn write_positions(
+        scylla: Data<scylla::Session>,
+        thread_id: i64,
+        mut post_ids: Vec<i64>,
+    ) -> Result<()> {
+        // Snowflakes sort by creation time.
+        post_ids.sort_unstable();
+        post_ids.dedup();
+        let last = post_ids.len() as i64;
+
+        scylla
+            .query(
+                "DELETE FROM volksforo.post_positions WHERE thread_id = ?",
+                (thread_id,),
+            )
+            .await?;
+
+        let mut queries = JoinSet::new();
+        for (index, post_id) in post_ids.into_iter().enumerate() {
+            let nscylla = scylla.to_owned();
+            queries.spawn(async move {
+                nscylla
+                    .query(
+                        "INSERT INTO volksforo.post_positions (thread_id, position, post_id) VALUES (?, ?, ?)",
+                        (thread_id, index as i64 + 1, post_id),
+                    )
+                    .await
+            });
+        }
+
+        while let Some(result) = queries.join_next().await {
+            result??;
+        }
+
+        Self::reset_sequence(scylla, thread_id, last).await
+    }
+
     pub async fn fetch_thread(
         scylla: Data<scylla::Session>,
         thread_id: i64,
         page: i64,
+        per_page: i64,
     ) -> Result<(Vec<Self>, HashMap<i64, i64>)> {
-        let start_pos = (page - 1) * 15;
+        let start_pos = (page - 1) * per_page;
         if let Some(rows) = scylla
             .query(
                 r#"SELECT thread_id, position, post_id
                     FROM volksforo.post_positions
                     WHERE thread_id = ? AND position > ? AND position < ?
                 ;"#,
-                (thread_id, start_pos - 1, start_pos + 15 + 1),
+                (thread_id, start_pos, start_pos + per_page + 1),
             )
             .await?
             .rows
@@ -128,25 +293,105 @@ impl Post {
         }
     }
 
-    pub async fn insert(&self, scylla: Data<scylla::Session>) -> Result<i64> {
-        let max_position = scylla
+    /// Reads the last position handed out in a thread.
+    async fn fetch_sequence(scylla: Data<scylla::Session>, thread_id: i64) -> Result<Option<i64>> {
+        // A stale read is harmless: advancing from it fails and is retried.
+        Ok(scylla
             .query(
-                r#"SELECT
-                    MAX(position)
-                    FROM volksforo.post_positions
-                    WHERE thread_id = ?
-                ;"#,
-                (&self.thread_id,),
+                "SELECT last_position FROM volksforo.thread_positions WHERE thread_id = ?",
+                (thread_id,),
             )
             .await?
             .rows
             .unwrap_or_default()
             .into_typed::<(i64,)>()
             .collect::<Result<Vec<(i64,)>, FromRowError>>()?
-            .first()
-            .map_or(0, |r| r.0);
+            .pop()
+            .map(|r| r.0))
+    }
+
+    /// Moves a thread's sequence from `current` to `next`, unless someone else moved it first.
+    async fn advance_sequence(
+        scylla: Data<scylla::Session>,
+        thread_id: i64,
+        current: Option<i64>,
+        next: i64,
+    ) -> Result<bool> {
+        let mut query = Query::new(match current {
+            None => {
+                r#"INSERT INTO volksforo.thread_positions (last_position, thread_id)
+                    VALUES (?, ?)
+                    IF NOT EXISTS
+                ;"#
+            }
+            Some(_) => {
+                r#"UPDATE volksforo.thread_positions
+                    SET last_position = ?
+                    WHERE thread_id = ?
+                    IF last_position = ?
+                ;"#
+            }
+        });
+        query.set_serial_consistency(Some(SerialConsistency::Serial));
+
+        let result = match current {
+            None => scylla.query(query, (next, thread_id)).await?,
+            Some(current) => scylla.query(query, (next, thread_id, current)).await?,
+        };
+
+        Ok(crate::util::is_applied(result))
+    }
+
+    /// Claims the next position in a thread. Positions come from a per-thread sequence advanced with
+    /// compare-and-set, so simultaneous replies never share a number.
+    /// Threads from before the sequence existed start it from their highest position.
+    pub async fn next_position(scylla: Data<scylla::Session>, thread_id: i64) -> Result<i64> {
+        for _ in 0..SEQUENCE_ATTEMPTS {
+            let current = Self::fetch_sequence(scylla.clone(), thread_id).await?;
+            let last = match current {
+                Some(last) => last,
+                None => Self::fetch_last_position(scylla.clone(), thread_id)
+                    .await?
+                    .unwrap_or(0),
+            };
+
+            if Self::advance_sequence(scylla.clone(), thread_id, current, last + 1).await? {
+                return Ok(last + 1);
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "No position could be claimed in thread {} after {} attempts",
+            thread_id,
+            SEQUENCE_ATTEMPTS
+        ))
+    }
+
+    /// Sets a thread's sequence so the next position handed out follows `last`.
+    async fn reset_sequence(
+        scylla: Data<scylla::Session>,
+        thread_id: i64,
+        last: i64,
+    ) -> Result<()> {
+        for _ in 0..SEQUENCE_ATTEMPTS {
+            let current = Self::fetch_sequence(scylla.clone(), thread_id).await?;
+            if current == Some(last)
+                || Self::advance_sequence(scylla.clone(), thread_id, current, last).await?
+            {
+                return Ok(());
+            }
+        }
 
-        let position = max_position + 1;
+        Err(anyhow::anyhow!(
+            "The sequence of thread {} could not be reset after {} attempts",
+            thread_id,
+            SEQUENCE_ATTEMPTS
+        ))
+    }
+
+    /// Inserts a post at the end of its thread. Returns its position.
+    pub async fn insert(&self, scylla: Data<scylla::Session>) -> Result<i64> {
+        let position = Self::next_position(scylla.clone(), self.thread_id).await?;
         let timestamp = chrono::Utc::now().timestamp_millis();
 
         let mut ins_pos = Query::new(
@@ -158,7 +403,6 @@ impl Post {
             VALUES (?, ?, ?);"#,
         );
         ins_pos.set_consistency(Consistency::One);
-        ins_pos.set_serial_consistency(Some(SerialConsistency::Serial));
 
         let ins_post = Query::new(
             r#"INSERT INTO volksforo.posts (
@@ -190,4 +434,82 @@ impl Post {
             (Err(err), Err(_)) => Err(err.into()),
         }
     }
+
+    /// Hides a post from everyone but moderators. Its position is kept so numbering doesn't shift.
+    /// Returns false if the post was already deleted.
+    pub async fn soft_delete(
+        &self,
+        scylla: Data<scylla::Session>,
+        deleted_by: Option<i64>,
+        reason: Option<String>,
+    ) -> Result<bool> {
+        let deleted = Deletion {
+            id: self.id,
+            deleted_by,
+            deleted_at: Duration::milliseconds(chrono::Utc::now().timestamp_millis()),
+            reason,
+        }
+        .insert_if_absent(scylla.clone(), DeletionKind::Post)
+        .await?;
+
+        if deleted {
+            Thread::increment_reply_count(scylla, self.thread_id, -1).await?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Undoes a soft delete. Returns false if the post was not deleted.
+    pub async fn restore(&self, scylla: Data<scylla::Session>) -> Result<bool> {
+        let restored = Deletion::remove(scylla.clone(), DeletionKind::Post, self.id).await?;
+
+        if restored {
+            Thread::increment_reply_count(scylla, self.thread_id, 1).await?;
+        }
+
+        Ok(restored)
+    }
+
+    /// Removes the post row, every revision of its content and its attachment links.
+    /// The position row is left behind so later posts keep their numbers.
+    pub(super) async fn delete_rows(&self, scylla: Data<scylla::Session>) -> Result<()> {
+        match tokio::join!(
+            Ugc::purge(scylla.clone(), &self.ugc_id),
+            scylla.query("DELETE FROM volksforo.posts WHERE id = ?", (self.id,)),
+            Attachment::detach_all_from_post(scylla.clone(), self.id),
+        ) {
+            (Ok(_), Ok(_), Ok(_)) => Ok(()),
+            (Err(err), _, _) => Err(err),
+            (_, Err(err), _) => Err(err.into()),
+            (_, _, Err(err)) => Err(err),
+        }
+    }
+
+    /// Permanently removes a post, for legal takedowns. Who did it and why is still recorded.
+    pub async fn purge(
+        &self,
+        scylla: Data<scylla::Session>,
+        deleted_by: Option<i64>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let was_deleted = Deletion::fetch(scylla.clone(), DeletionKind::Post, self.id)
+            .await?
+            .is_some();
+
+        self.delete_rows(scylla.clone()).await?;
+        Deletion {
+            id: self.id,
+            deleted_by,
+            deleted_at: Duration::milliseconds(chrono::Utc::now().timestamp_millis()),
+            reason,
+        }
+        .insert(scylla.clone(), DeletionKind::Post)
+        .await?;
+
+        if !was_deleted {
+            Thread::increment_reply_count(scylla, self.thread_id, -1).await?;
+        }
+
+        Ok(())
+    }
 }
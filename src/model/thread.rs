@@ -1,10 +1,46 @@
+use super::deletion::{Deletion, DeletionKind};
+use super::{Node, Post};
 use actix_web::web::Data;
 use anyhow::Result;
 use chrono::Duration;
+use scylla::batch::{Batch, BatchType};
+use scylla::query::Query;
+use scylla::statement::SerialConsistency;
 use scylla::{cql_to_rust::FromRowError, frame::value, FromRow, IntoTypedRows, Session};
 use std::collections::HashMap;
 use tokio::task::JoinSet;
 
+/// Width of a thread listing bucket. Threads are bucketed by when they were last active.
+pub const BUCKET_MILLIS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// How many times a bump or other change to a thread row is attempted before giving up.
+/// Every failed attempt means another reply moved the thread first, so this only runs out under heavy contention.
+const BUMP_ATTEMPTS: usize = 8;
+
+/// Threads read at a time while walking past earlier pages of a forum.
+const NODE_WINDOW: i32 = 500;
+
+/// Returns the listing bucket for activity at this unix timestamp (in milliseconds).
+pub fn get_bucket_for_timestamp(millis: i64) -> i32 {
+    (millis / BUCKET_MILLIS) as i32
+}
+
+/// A thread's place in its forum's listing, which is ordered by its clustering key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct NodeCursor {
+    bucket_id: i32,
+    last_post_id: i64,
+}
+
+impl NodeCursor {
+    fn of(thread: &Thread) -> Self {
+        Self {
+            bucket_id: thread.bucket_id,
+            last_post_id: thread.last_post_id,
+        }
+    }
+}
+
 #[derive(Debug, FromRow)]
 pub struct Thread {
     pub id: i64,
@@ -14,23 +50,756 @@ pub struct Thread {
     pub subtitle: Option<String>,
     pub created_at: Duration,
     pub first_post_id: i64,
-    pub first_post_user_id: i64,
+    pub first_post_user_id: Option<i64>,
     pub last_post_id: i64,
-    pub last_post_user_id: i64,
+    pub last_post_user_id: Option<i64>,
+    /// Locked threads only accept replies from moderators.
+    pub locked: bool,
+    /// Sticky threads are listed above all others on the first page of their forum.
+    pub sticky: bool,
+    /// Set on the stub left behind when a thread is moved or merged. Visitors are sent here instead.
+    pub redirect_thread_id: Option<i64>,
 }
 
 impl Thread {
-    /// Adds to a thread's view count without blocking.
-    pub fn bump_view_count(&self, scylla: Data<Session>) {
-        let thread_id = self.id.to_owned();
-        tokio::spawn(async move {
+    /// Inserts a new thread row. Its first post must already exist.
+    pub async fn insert(&self, scylla: Data<Session>) -> Result<()> {
+        scylla
+            .query(
+                r#"INSERT INTO volksforo.threads (
+                    id,
+                    node_id,
+                    bucket_id,
+                    title,
+                    subtitle,
+                    created_at,
+                    first_post_id,
+                    first_post_user_id,
+                    last_post_id,
+                    last_post_user_id,
+                    locked,
+                    sticky,
+                    redirect_thread_id
+                )
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ;"#,
+                (
+                    self.id,
+                    self.node_id,
+                    self.bucket_id,
+                    &self.title,
+                    &self.subtitle,
+                    self.created_at.num_milliseconds(),
+                    self.first_post_id,
+                    self.first_post_user_id,
+                    self.last_post_id,
+                    self.last_post_user_id,
+                    self.locked,
+                    self.sticky,
+                    self.redirect_thread_id,
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// The primary key of this thread's row.
+    fn key(&self) -> (i64, i32, i64) {
+        (self.node_id, self.bucket_id, self.last_post_id)
+    }
+
+    /// Replaces the row at `key` with this thread, which must be in the same node.
+    /// Threads are keyed on (bucket_id, last_post_id), which cannot be updated in place, so the old row is
+    /// deleted and a new one inserted in a conditional batch.
+    /// Returns false if the old row is gone because something else moved the thread first.
+    async fn replace(&self, scylla: Data<Session>, key: (i64, i32, i64)) -> Result<bool> {
+        let mut batch = Batch::new(BatchType::Logged);
+        batch.set_serial_consistency(Some(SerialConsistency::Serial));
+        batch.append_statement(
+            r#"DELETE FROM volksforo.threads
+                WHERE node_id = ? AND bucket_id = ? AND last_post_id = ?
+                IF EXISTS
+            ;"#,
+        );
+        batch.append_statement(
+            r#"INSERT INTO volksforo.threads (
+                id,
+                node_id,
+                bucket_id,
+                title,
+                subtitle,
+                created_at,
+                first_post_id,
+                first_post_user_id,
+                last_post_id,
+                last_post_user_id,
+                locked,
+                sticky,
+                redirect_thread_id
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ;"#,
+        );
+
+        let result = scylla
+            .batch(
+                &batch,
+                (
+                    key,
+                    (
+                        self.id,
+                        self.node_id,
+                        self.bucket_id,
+                        &self.title,
+                        &self.subtitle,
+                        self.created_at.num_milliseconds(),
+                        self.first_post_id,
+                        self.first_post_user_id,
+                        self.last_post_id,
+                        self.last_post_user_id,
+                        self.locked,
+                        self.sticky,
+                        self.redirect_thread_id,
+                    ),
+                ),
+            )
+            .await?;
+
+        Ok(crate::util::is_applied(result))
+    }
+
+    /// Writes everything but the key back to this thread's row.
+    /// Returns false if the row is gone because something else moved the thread first.
+    async fn update_in_place(&self, scylla: Data<Session>) -> Result<bool> {
+        let mut query = Query::new(
+            r#"UPDATE volksforo.threads SET
+                    title = ?,
+                    subtitle = ?,
+                    created_at = ?,
+                    first_post_id = ?,
+                    first_post_user_id = ?,
+                    last_post_user_id = ?,
+                    locked = ?,
+                    sticky = ?,
+                    redirect_thread_id = ?
+                WHERE node_id = ? AND bucket_id = ? AND last_post_id = ?
+                IF EXISTS
+            ;"#,
+        );
+        query.set_serial_consistency(Some(SerialConsistency::Serial));
+
+        let result = scylla
+            .query(
+                query,
+                (
+                    &self.title,
+                    &self.subtitle,
+                    self.created_at.num_milliseconds(),
+                    self.first_post_id,
+                    self.first_post_user_id,
+                    self.last_post_user_id,
+                    self.locked,
+                    self.sticky,
+                    self.redirect_thread_id,
+                    self.node_id,
+                    self.bucket_id,
+                    self.last_post_id,
+                ),
+            )
+            .await?;
+
+        Ok(crate::util::is_applied(result))
+    }
+
+    /// Reads a thread, applies `change` and writes it back, reading again and retrying if a reply moved
+    /// the thread in the meantime. `change` may move the thread within its forum but not to another one.
+    pub async fn modify(
+        scylla: Data<Session>,
+        thread_id: i64,
+        change: impl Fn(&mut Self),
+    ) -> Result<Self> {
+        for _ in 0..BUMP_ATTEMPTS {
+            let mut thread = Self::fetch(scylla.clone(), &thread_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Thread {} does not exist", thread_id))?;
+            let key = thread.key();
+
+            change(&mut thread);
+            if thread.node_id != key.0 {
+                return Err(anyhow::anyhow!(
+                    "Thread {} cannot change forum in place",
+                    thread_id
+                ));
+            }
+
+            let applied = if thread.key() == key {
+                thread.update_in_place(scylla.clone()).await?
+            } else {
+                thread.replace(scylla.clone(), key).await?
+            };
+            if applied {
+                return Ok(thread);
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Thread {} could not be changed after {} attempts",
+            thread_id,
+            BUMP_ATTEMPTS
+        ))
+    }
+
+    /// Deletes a thread's listing row, reading it again and retrying if a reply moved it in the meantime.
+    /// Returns the thread as it was deleted, or None if it was already gone.
+    async fn delete_row(scylla: Data<Session>, thread_id: i64) -> Result<Option<Self>> {
+        let mut delete = Query::new(
+            r#"DELETE FROM volksforo.threads
+                WHERE node_id = ? AND bucket_id = ? AND last_post_id = ?
+                IF EXISTS
+            ;"#,
+        );
+        delete.set_serial_consistency(Some(SerialConsistency::Serial));
+
+        for _ in 0..BUMP_ATTEMPTS {
+            let thread = match Self::fetch(scylla.clone(), &thread_id).await? {
+                Some(thread) => thread,
+                None => return Ok(None),
+            };
+
+            let result = scylla.query(delete.clone(), thread.key()).await?;
+            if crate::util::is_applied(result) {
+                return Ok(Some(thread));
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Thread {} could not be deleted after {} attempts",
+            thread_id,
+            BUMP_ATTEMPTS
+        ))
+    }
+
+    /// Moves a thread to the clustering key of its newest reply so forum listings are ordered by activity.
+    /// If another reply moved the thread first, we read the thread again and retry.
+    /// A reply older than the thread's current last post leaves it where it is.
+    pub async fn bump(
+        scylla: Data<Session>,
+        thread_id: i64,
+        post_id: i64,
+        user_id: Option<i64>,
+        timestamp: i64,
+    ) -> Result<()> {
+        for _ in 0..BUMP_ATTEMPTS {
+            let thread = Self::fetch(scylla.clone(), &thread_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Thread {} does not exist", thread_id))?;
+
+            if thread.last_post_id >= post_id {
+                return Ok(());
+            }
+
+            let key = thread.key();
+            let bumped = Self {
+                bucket_id: get_bucket_for_timestamp(timestamp),
+                last_post_id: post_id,
+                last_post_user_id: user_id,
+                ..thread
+            };
+
+            if bumped.replace(scylla.clone(), key).await? {
+                return Ok(());
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Thread {} could not be bumped after {} attempts",
+            thread_id,
+            BUMP_ATTEMPTS
+        ))
+    }
+
+    /// Hides a thread and its posts from everyone but moderators.
+    /// Returns false if the thread was already deleted.
+    pub async fn soft_delete(
+        &self,
+        scylla: Data<Session>,
+        deleted_by: Option<i64>,
+        reason: Option<String>,
+    ) -> Result<bool> {
+        let deleted = Deletion {
+            id: self.id,
+            deleted_by,
+            deleted_at: Duration::milliseconds(chrono::Utc::now().timestamp_millis()),
+            reason,
+        }
+        .insert_if_absent(scylla.clone(), DeletionKind::Thread)
+        .await?;
+
+        if deleted {
+            Node::increment_thread_count(scylla, self.node_id, -1).await?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Undoes a soft delete. Returns false if the thread was not deleted.
+    pub async fn restore(&self, scylla: Data<Session>) -> Result<bool> {
+        let restored = Deletion::remove(scylla.clone(), DeletionKind::Thread, self.id).await?;
+
+        if restored {
+            Node::increment_thread_count(scylla, self.node_id, 1).await?;
+        }
+
+        Ok(restored)
+    }
+
+    /// Permanently removes a thread and all of its posts, for legal takedowns.
+    /// Who did it and why is still recorded.
+    pub async fn purge(
+        &self,
+        scylla: Data<Session>,
+        deleted_by: Option<i64>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let was_deleted = Deletion::fetch(scylla.clone(), DeletionKind::Thread, self.id)
+            .await?
+            .is_some();
+
+        // The listing row goes first, so replies find the thread gone instead of moving it.
+        Self::delete_row(scylla.clone(), self.id).await?;
+
+        let post_ids = Post::fetch_thread_ids(scylla.clone(), self.id).await?;
+        for post in Post::fetch_many(scylla.clone(), post_ids).await? {
+            post.delete_rows(scylla.clone()).await?;
+        }
+
+        match tokio::join!(
+            scylla.query(
+                "DELETE FROM volksforo.post_positions WHERE thread_id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.thread_positions WHERE thread_id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.thread_replies WHERE id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.thread_views WHERE id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.sticky_threads WHERE node_id = ? AND thread_id = ?",
+                (self.node_id, self.id)
+            ),
+        ) {
+            (Ok(_), Ok(_), Ok(_), Ok(_), Ok(_)) => {}
+            (Err(err), _, _, _, _) => return Err(err.into()),
+            (_, Err(err), _, _, _) => return Err(err.into()),
+            (_, _, Err(err), _, _) => return Err(err.into()),
+            (_, _, _, Err(err), _) => return Err(err.into()),
+            (_, _, _, _, Err(err)) => return Err(err.into()),
+        }
+
+        Deletion {
+            id: self.id,
+            deleted_by,
+            deleted_at: Duration::milliseconds(chrono::Utc::now().timestamp_millis()),
+            reason,
+        }
+        .insert(scylla.clone(), DeletionKind::Thread)
+        .await?;
+
+        if !was_deleted {
+            Node::increment_thread_count(scylla, self.node_id, -1).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pins or unpins a thread at the top of its forum.
+    pub async fn set_sticky(scylla: Data<Session>, thread_id: i64, sticky: bool) -> Result<Self> {
+        let thread = Self::modify(scylla.clone(), thread_id, |t| t.sticky = sticky).await?;
+
+        if sticky {
+            scylla
+                .query(
+                    "INSERT INTO volksforo.sticky_threads (node_id, thread_id) VALUES (?, ?)",
+                    (thread.node_id, thread.id),
+                )
+                .await?;
+        } else {
             scylla
                 .query(
-                    "UPDATE volksforo.thread_views SET view_count = view_count + 1 WHERE id = ?",
-                    (thread_id,),
+                    "DELETE FROM volksforo.sticky_threads WHERE node_id = ? AND thread_id = ?",
+                    (thread.node_id, thread.id),
                 )
-                .await
-        });
+                .await?;
+        }
+
+        Ok(thread)
+    }
+
+    /// Returns true if a thread is soft deleted, so it is not in its forum's thread count.
+    async fn is_deleted(scylla: Data<Session>, thread_id: i64) -> Result<bool> {
+        Ok(Deletion::fetch(scylla, DeletionKind::Thread, thread_id)
+            .await?
+            .is_some())
+    }
+
+    /// Leaves a stub in this thread's place which sends visitors to `target_id`.
+    async fn insert_redirect(&self, scylla: Data<Session>, target_id: i64) -> Result<Self> {
+        let stub = Self {
+            id: crate::util::snowflake_id().await?,
+            title: self.title.to_owned(),
+            subtitle: self.subtitle.to_owned(),
+            locked: true,
+            sticky: false,
+            redirect_thread_id: Some(target_id),
+            ..*self
+        };
+        stub.insert(scylla.clone()).await?;
+        Node::increment_thread_count(scylla, stub.node_id, 1).await?;
+
+        Ok(stub)
+    }
+
+    /// Moves a thread to another forum. The forum is the partition key, so the row is deleted and
+    /// inserted again. The old row is deleted first: a reply arriving in between fails its bump and
+    /// retries against the new row.
+    /// If `leave_redirect` is set, a stub pointing to the thread is left in the old forum.
+    pub async fn move_to_node(
+        scylla: Data<Session>,
+        thread_id: i64,
+        node_id: i64,
+        leave_redirect: bool,
+    ) -> Result<Self> {
+        let mut delete = Query::new(
+            r#"DELETE FROM volksforo.threads
+                WHERE node_id = ? AND bucket_id = ? AND last_post_id = ?
+                IF EXISTS
+            ;"#,
+        );
+        delete.set_serial_consistency(Some(SerialConsistency::Serial));
+
+        for _ in 0..BUMP_ATTEMPTS {
+            let thread = Self::fetch(scylla.clone(), &thread_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Thread {} does not exist", thread_id))?;
+            if thread.node_id == node_id {
+                return Ok(thread);
+            }
+
+            let result = scylla.query(delete.clone(), thread.key()).await?;
+            if !crate::util::is_applied(result) {
+                continue;
+            }
+
+            let old_node_id = thread.node_id;
+            let moved = Self { node_id, ..thread };
+            moved.insert(scylla.clone()).await?;
+
+            if moved.sticky {
+                match tokio::join!(
+                    scylla.query(
+                        "DELETE FROM volksforo.sticky_threads WHERE node_id = ? AND thread_id = ?",
+                        (old_node_id, moved.id),
+                    ),
+                    scylla.query(
+                        "INSERT INTO volksforo.sticky_threads (node_id, thread_id) VALUES (?, ?)",
+                        (moved.node_id, moved.id),
+                    ),
+                ) {
+                    (Ok(_), Ok(_)) => {}
+                    (Err(err), _) => return Err(err.into()),
+                    (_, Err(err)) => return Err(err.into()),
+                }
+            }
+
+            if !Self::is_deleted(scylla.clone(), moved.id).await? {
+                match tokio::join!(
+                    Node::increment_thread_count(scylla.clone(), old_node_id, -1),
+                    Node::increment_thread_count(scylla.clone(), moved.node_id, 1),
+                ) {
+                    (Ok(_), Ok(_)) => {}
+                    (Err(err), _) => return Err(err),
+                    (_, Err(err)) => return Err(err),
+                }
+            }
+
+            if leave_redirect {
+                Self {
+                    node_id: old_node_id,
+                    title: moved.title.to_owned(),
+                    subtitle: moved.subtitle.to_owned(),
+                    ..moved
+                }
+                .insert_redirect(scylla.clone(), moved.id)
+                .await?;
+            }
+
+            return Ok(moved);
+        }
+
+        Err(anyhow::anyhow!(
+            "Thread {} could not be moved after {} attempts",
+            thread_id,
+            BUMP_ATTEMPTS
+        ))
+    }
+
+    /// Corrects a thread's reply count to the number of visible posts among `post_ids`.
+    /// Returns the correction applied, which is 0 if the count was right.
+    pub async fn sync_reply_count(
+        scylla: Data<Session>,
+        thread_id: i64,
+        post_ids: Vec<i64>,
+    ) -> Result<i64> {
+        let total = post_ids.len() as i64;
+        let (deletions, current) = match tokio::join!(
+            Deletion::fetch_many(scylla.clone(), DeletionKind::Post, post_ids),
+            Self::fetch_reply_count(scylla.clone(), thread_id),
+        ) {
+            (Ok(deletions), Ok(current)) => (deletions, current.unwrap_or(0)),
+            (Err(err), _) => return Err(err),
+            (_, Err(err)) => return Err(err),
+        };
+
+        let correction = total - deletions.len() as i64 - current;
+        if correction != 0 {
+            Self::increment_reply_count(scylla, thread_id, correction).await?;
+        }
+
+        Ok(correction)
+    }
+
+    /// Moves every post of this thread into another and renumbers the other thread's posts by age.
+    /// This thread is left as a stub pointing to the other if `leave_redirect` is set, and removed otherwise.
+    pub async fn merge_into(
+        &self,
+        scylla: Data<Session>,
+        target_id: i64,
+        leave_redirect: bool,
+    ) -> Result<Self> {
+        if target_id == self.id {
+            return Err(anyhow::anyhow!(
+                "Thread {} cannot merge into itself",
+                self.id
+            ));
+        }
+
+        // Replies to the source are refused while it is merged, or they would be left without a position.
+        let source = Self::modify(scylla.clone(), self.id, |t| t.locked = true).await?;
+
+        let (source_ids, target_ids) = match tokio::join!(
+            Post::fetch_thread_ids(scylla.clone(), source.id),
+            Post::fetch_thread_ids(scylla.clone(), target_id),
+        ) {
+            (Ok(source_ids), Ok(target_ids)) => (source_ids, target_ids),
+            (Err(err), _) => return Err(err),
+            (_, Err(err)) => return Err(err),
+        };
+
+        // Positions outlive purged posts, so only posts which still exist are carried over.
+        let mut post_ids: Vec<i64> = Post::fetch_many(scylla.clone(), source_ids)
+            .await?
+            .iter()
+            .map(|post| post.id)
+            .collect();
+        Post::set_thread(scylla.clone(), &post_ids, target_id).await?;
+        post_ids.extend(
+            Post::fetch_many(scylla.clone(), target_ids)
+                .await?
+                .iter()
+                .map(|post| post.id),
+        );
+        Post::write_positions(scylla.clone(), target_id, post_ids.to_owned()).await?;
+        Self::sync_reply_count(scylla.clone(), target_id, post_ids).await?;
+
+        // The merged thread starts with the oldest post of either and ends with the newest.
+        let target = Self::modify(scylla.clone(), target_id, |t| {
+            if source.first_post_id < t.first_post_id {
+                t.created_at = source.created_at;
+                t.first_post_id = source.first_post_id;
+                t.first_post_user_id = source.first_post_user_id;
+            }
+            if source.last_post_id > t.last_post_id {
+                t.bucket_id = source.bucket_id;
+                t.last_post_id = source.last_post_id;
+                t.last_post_user_id = source.last_post_user_id;
+            }
+        })
+        .await?;
+
+        match tokio::join!(
+            scylla.query(
+                "DELETE FROM volksforo.post_positions WHERE thread_id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.thread_positions WHERE thread_id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.thread_replies WHERE id = ?",
+                (self.id,)
+            ),
+            scylla.query(
+                "DELETE FROM volksforo.sticky_threads WHERE node_id = ? AND thread_id = ?",
+                (self.node_id, self.id)
+            ),
+        ) {
+            (Ok(_), Ok(_), Ok(_), Ok(_)) => {}
+            (Err(err), _, _, _) => return Err(err.into()),
+            (_, Err(err), _, _) => return Err(err.into()),
+            (_, _, Err(err), _) => return Err(err.into()),
+            (_, _, _, Err(err)) => return Err(err.into()),
+        }
+
+        if leave_redirect {
+            Self::modify(scylla.clone(), self.id, |t| {
+                t.locked = true;
+                t.sticky = false;
+                t.redirect_thread_id = Some(target_id);
+            })
+            .await?;
+        } else if Self::delete_row(scylla.clone(), self.id).await?.is_some()
+            && !Self::is_deleted(scylla.clone(), self.id).await?
+        {
+            Node::increment_thread_count(scylla, self.node_id, -1).await?;
+        }
+
+        Ok(target)
+    }
+
+    /// Moves some posts of this thread into a new thread and renumbers both.
+    /// The first post has to stay, or this thread would be left without one.
+    pub async fn split(
+        &self,
+        scylla: Data<Session>,
+        post_ids: &[i64],
+        node_id: i64,
+        title: String,
+    ) -> Result<Self> {
+        let mut staying = Vec::new();
+        let mut leaving = Vec::new();
+        for post in Post::fetch_many(
+            scylla.clone(),
+            Post::fetch_thread_ids(scylla.clone(), self.id).await?,
+        )
+        .await?
+        {
+            match post_ids.contains(&post.id) {
+                true => leaving.push(post),
+                false => staying.push(post),
+            }
+        }
+
+        if leaving.is_empty() {
+            return Err(anyhow::anyhow!("No posts in thread {} to split", self.id));
+        }
+        if leaving.iter().any(|post| post.id == self.first_post_id) {
+            return Err(anyhow::anyhow!(
+                "The first post of thread {} cannot be split off",
+                self.id
+            ));
+        }
+
+        // fetch_many returns posts sorted by id.
+        let first = leaving.first().expect("leaving is not empty");
+        let last = leaving.last().expect("leaving is not empty");
+        let thread = Self {
+            id: crate::util::snowflake_id().await?,
+            node_id,
+            bucket_id: get_bucket_for_timestamp(last.created_at.num_milliseconds()),
+            title,
+            subtitle: None,
+            created_at: first.created_at,
+            first_post_id: first.id,
+            first_post_user_id: first.user_id,
+            last_post_id: last.id,
+            last_post_user_id: last.user_id,
+            locked: false,
+            sticky: false,
+            redirect_thread_id: None,
+        };
+        thread.insert(scylla.clone()).await?;
+        Node::increment_thread_count(scylla.clone(), node_id, 1).await?;
+
+        let leaving_ids: Vec<i64> = leaving.iter().map(|post| post.id).collect();
+        let staying_ids: Vec<i64> = staying.iter().map(|post| post.id).collect();
+        Post::set_thread(scylla.clone(), &leaving_ids, thread.id).await?;
+        match tokio::join!(
+            Post::write_positions(scylla.clone(), thread.id, leaving_ids.to_owned()),
+            Post::write_positions(scylla.clone(), self.id, staying_ids.to_owned()),
+            Self::sync_reply_count(scylla.clone(), thread.id, leaving_ids),
+            Self::sync_reply_count(scylla.clone(), self.id, staying_ids),
+        ) {
+            (Ok(_), Ok(_), Ok(_), Ok(_)) => {}
+            (Err(err), _, _, _) => return Err(err),
+            (_, Err(err), _, _) => return Err(err),
+            (_, _, Err(err), _) => return Err(err),
+            (_, _, _, Err(err)) => return Err(err),
+        }
+
+        // If the newest post left, this thread is now only as recent as the newest one remaining.
+        let newest = staying.last().expect("the first post stays");
+        if newest.id != self.last_post_id {
+            Self::modify(scylla.clone(), self.id, |t| {
+                t.bucket_id = get_bucket_for_timestamp(newest.created_at.num_milliseconds());
+                t.last_post_id = newest.id;
+                t.last_post_user_id = newest.user_id;
+            })
+            .await?;
+        }
+
+        Ok(thread)
+    }
+
+    /// Renumbers a thread's posts from 1 in the order they were made and corrects its reply count.
+    /// This fixes duplicate or missing positions and drops positions left behind by purged posts.
+    /// Returns the number of posts in the thread.
+    pub async fn repair(scylla: Data<Session>, thread_id: i64) -> Result<i64> {
+        // The first and last posts are included in case their positions were lost.
+        let thread = Self::fetch(scylla.clone(), &thread_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Thread {} does not exist", thread_id))?;
+        let mut post_ids = Post::fetch_thread_ids(scylla.clone(), thread_id).await?;
+        post_ids.extend([thread.first_post_id, thread.last_post_id]);
+        post_ids.sort_unstable();
+        post_ids.dedup();
+
+        let post_ids: Vec<i64> = Post::fetch_many(scylla.clone(), post_ids)
+            .await?
+            .iter()
+            .filter(|post| post.thread_id == thread_id)
+            .map(|post| post.id)
+            .collect();
+        let count = post_ids.len() as i64;
+
+        Post::write_positions(scylla.clone(), thread_id, post_ids.to_owned()).await?;
+        Self::sync_reply_count(scylla, thread_id, post_ids).await?;
+
+        Ok(count)
+    }
+
+    /// Adjusts a thread's reply count. Counters can only be incremented or decremented.
+    pub async fn increment_reply_count(
+        scylla: Data<Session>,
+        thread_id: i64,
+        amount: i64,
+    ) -> Result<()> {
+        scylla
+            .query(
+                "UPDATE volksforo.thread_replies SET reply_count = reply_count + ? WHERE id = ?",
+                (value::Counter(amount), thread_id),
+            )
+            .await?;
+
+        Ok(())
     }
 
     ///  Returns a single thread.
@@ -47,7 +816,10 @@ impl Thread {
                         first_post_id,
                         first_post_user_id,
                         last_post_id,
-                        last_post_user_id
+                        last_post_user_id,
+                        locked,
+                        sticky,
+                        redirect_thread_id
                     FROM volksforo.threads
                     WHERE id = ?
                 ;"#,
@@ -61,42 +833,170 @@ impl Thread {
             .pop())
     }
 
-    /// Returns all threads for a forum page.
-    pub async fn fetch_node_page(
-        scylla: Data<Session>,
-        node_id: i64,
-        bucket_id: i32,
-    ) -> Result<Vec<Self>> {
-        if let Some(rows) = scylla
+    /// Returns many threads by id, in no particular order.
+    pub async fn fetch_many(scylla: Data<Session>, thread_ids: Vec<i64>) -> Result<Vec<Self>> {
+        let mut queries = JoinSet::new();
+
+        for thread_id in thread_ids {
+            let nscylla = scylla.to_owned();
+            queries.spawn(async move { Self::fetch(nscylla, &thread_id).await });
+        }
+
+        let mut threads = Vec::with_capacity(queries.len());
+        while let Some(result) = queries.join_next().await {
+            if let Some(thread) = result?? {
+                threads.push(thread);
+            }
+        }
+
+        Ok(threads)
+    }
+
+    /// Returns a forum's sticky threads, most recently active first.
+    pub async fn fetch_sticky(scylla: Data<Session>, node_id: i64) -> Result<Vec<Self>> {
+        let thread_ids = scylla
             .query(
-                "SELECT
-                        id,
-                        node_id,
-                        bucket_id,
-                        title,
-                        subtitle,
-                        created_at,
-                        first_post_id,
-                        first_post_user_id,
-                        last_post_id,
-                        last_post_user_id
-                    FROM volksforo.threads
-                    WHERE node_id = ? AND bucket_id = ?",
-                (node_id, bucket_id),
+                "SELECT thread_id FROM volksforo.sticky_threads WHERE node_id = ?",
+                (node_id,),
             )
             .await?
             .rows
-        {
-            let mut result = Vec::<Self>::with_capacity(rows.len());
+            .unwrap_or_default()
+            .into_typed::<(i64,)>()
+            .map(|r| r.map(|r| r.0))
+            .collect::<Result<Vec<i64>, FromRowError>>()?;
+
+        let mut threads = Self::fetch_many(scylla, thread_ids).await?;
+        threads.sort_by_key(|t| std::cmp::Reverse(t.last_post_id));
+
+        Ok(threads)
+    }
 
-            for row in rows.into_typed::<Self>() {
-                result.push(row?);
+    /// Returns one page of a forum's threads, most recently active first.
+    /// Scylla has no OFFSET, so earlier pages are walked in windows by their clustering key.
+    /// Sticky threads, and deleted ones unless `include_deleted`, are skipped so pages are full.
+    pub async fn fetch_node_page(
+        scylla: Data<Session>,
+        node_id: i64,
+        page: i64,
+        per_page: i32,
+        include_deleted: bool,
+    ) -> Result<Vec<Self>> {
+        let mut skip = (std::cmp::max(1, page) - 1) * per_page as i64;
+        let mut threads = Vec::with_capacity(per_page as usize);
+        let mut cursor = None;
+        loop {
+            // Threads before the page are only counted, so they are read in larger windows.
+            let limit = if skip > 0 { NODE_WINDOW } else { per_page };
+            let window = Self::fetch_node_window(scylla.clone(), node_id, cursor, limit).await?;
+            let exhausted = window.len() < limit as usize;
+            cursor = window.last().map(NodeCursor::of);
+
+            let deleted = match include_deleted {
+                true => HashMap::new(),
+                false => {
+                    Deletion::fetch_many(
+                        scylla.clone(),
+                        DeletionKind::Thread,
+                        window.iter().map(|t| t.id).collect(),
+                    )
+                    .await?
+                }
+            };
+            for thread in window {
+                if thread.sticky || deleted.contains_key(&thread.id) {
+                    continue;
+                }
+                if skip > 0 {
+                    skip -= 1;
+                } else if threads.len() < per_page as usize {
+                    threads.push(thread);
+                }
             }
 
-            return Ok(result);
+            if exhausted || threads.len() >= per_page as usize {
+                return Ok(threads);
+            }
         }
+    }
+
+    /// Returns up to `limit` of a forum's threads, starting after the cursor if there is one.
+    async fn fetch_node_window(
+        scylla: Data<Session>,
+        node_id: i64,
+        after: Option<NodeCursor>,
+        limit: i32,
+    ) -> Result<Vec<Self>> {
+        const COLUMNS: &str = r#"id,
+                node_id,
+                bucket_id,
+                title,
+                subtitle,
+                created_at,
+                first_post_id,
+                first_post_user_id,
+                last_post_id,
+                last_post_user_id,
+                locked,
+                sticky,
+                redirect_thread_id"#;
+
+        let result = match after {
+            None => {
+                scylla
+                    .query(
+                        format!(
+                            r#"SELECT {}
+                                FROM volksforo.threads
+                                WHERE node_id = ?
+                                ORDER BY bucket_id DESC, last_post_id DESC
+                                LIMIT ?
+                            ;"#,
+                            COLUMNS
+                        ),
+                        (node_id, limit),
+                    )
+                    .await?
+            }
+            Some(cursor) => {
+                scylla
+                    .query(
+                        format!(
+                            r#"SELECT {}
+                                FROM volksforo.threads
+                                WHERE node_id = ? AND (bucket_id, last_post_id) < (?, ?)
+                                ORDER BY bucket_id DESC, last_post_id DESC
+                                LIMIT ?
+                            ;"#,
+                            COLUMNS
+                        ),
+                        (node_id, cursor.bucket_id, cursor.last_post_id, limit),
+                    )
+                    .await?
+            }
+        };
+
+        Ok(result
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?)
+    }
+
+    /// Adds to a thread's view count. Views are collected by `crate::views::ViewCounter` and written in bulk.
+    pub async fn increment_view_count(
+        scylla: Data<Session>,
+        thread_id: i64,
+        amount: i64,
+    ) -> Result<()> {
+        scylla
+            .query(
+                "UPDATE volksforo.thread_views SET view_count = view_count + ? WHERE id = ?",
+                (value::Counter(amount), thread_id),
+            )
+            .await?;
 
-        Ok(Default::default())
+        Ok(())
     }
 
     /// Fetches the reply count of many threads.
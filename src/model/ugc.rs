@@ -2,12 +2,27 @@ use crate::session::Visitor;
 use actix_web::web::Data;
 use anyhow::Result;
 use chrono::Duration;
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
 use scylla::cql_to_rust::FromRowError;
 use scylla::{FromRow, IntoTypedRows, Session};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::task::JoinSet;
 use uuid::Uuid;
 
+/// Rendered content keyed by UGC id and revision timestamp. Revisions never change once written.
+static RENDER_CACHE: Lazy<DashMap<(Uuid, i64), Arc<str>>> = Lazy::new(DashMap::new);
+
+/// Most rendered revisions held in [RENDER_CACHE]. Zero disables the cache.
+static RENDER_CACHE_SIZE: Lazy<usize> = Lazy::new(|| {
+    std::env::var("VF_RENDER_CACHE_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(10_000)
+});
+
 #[derive(Debug, FromRow)]
 pub struct Ugc {
     pub id: Uuid,
@@ -18,12 +33,41 @@ pub struct Ugc {
 }
 
 impl Ugc {
+    /// Returns the content formatted as HTML, which is safe to output unescaped.
+    pub fn to_html(&self) -> Arc<str> {
+        if *RENDER_CACHE_SIZE == 0 {
+            return crate::bbcode::render(&self.content).into();
+        }
+
+        let key = (self.id, self.created_at.num_milliseconds());
+        if let Some(html) = RENDER_CACHE.get(&key) {
+            return html.clone();
+        }
+
+        let html: Arc<str> = crate::bbcode::render(&self.content).into();
+        // Crude, but entries are cheap to rebuild and a full cache is rare.
+        if RENDER_CACHE.len() >= *RENDER_CACHE_SIZE {
+            RENDER_CACHE.clear();
+        }
+        RENDER_CACHE.insert(key, html.clone());
+        html
+    }
+
     pub async fn create_for_visitor(
         scylla: Data<Session>,
         visitor: &Visitor,
         content: String,
     ) -> Result<Self> {
-        let uuid = Uuid::new_v4();
+        Self::create_revision(scylla, Uuid::new_v4(), visitor, content).await
+    }
+
+    /// Writes new content for a UGC id. Rows are clustered by time, so older revisions are kept.
+    pub async fn create_revision(
+        scylla: Data<Session>,
+        uuid: Uuid,
+        visitor: &Visitor,
+        content: String,
+    ) -> Result<Self> {
         let user_id = visitor.user.as_ref().map(|u| Some(u.id));
         let timestamp = chrono::Utc::now().timestamp_millis();
 
@@ -50,10 +94,15 @@ impl Ugc {
         }
     }
 
+    /// Returns the latest revision.
     pub async fn fetch(scylla: Data<scylla::Session>, uuid: &Uuid) -> Result<Option<Self>> {
         Ok(scylla
             .query(
-                "SELECT id, ip_id, user_id, created_at, content FROM volksforo.ugc WHERE id = ?",
+                r#"SELECT id, ip_id, user_id, created_at, content
+                    FROM volksforo.ugc
+                    WHERE id = ?
+                    LIMIT 1
+                ;"#,
                 (uuid,),
             )
             .await?
@@ -64,6 +113,54 @@ impl Ugc {
             .pop())
     }
 
+    /// Removes every revision. Used for legal takedowns; nothing is kept.
+    pub async fn purge(scylla: Data<Session>, uuid: &Uuid) -> Result<()> {
+        scylla
+            .query("DELETE FROM volksforo.ugc WHERE id = ?", (uuid,))
+            .await?;
+        RENDER_CACHE.retain(|(id, _), _| id != uuid);
+
+        Ok(())
+    }
+
+    /// Returns every revision, newest first.
+    pub async fn fetch_revisions(scylla: Data<Session>, uuid: &Uuid) -> Result<Vec<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT id, ip_id, user_id, created_at, content
+                    FROM volksforo.ugc
+                    WHERE id = ?
+                ;"#,
+                (uuid,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?)
+    }
+
+    /// Returns every revision a user wrote, of any content type.
+    pub async fn fetch_for_user(scylla: Data<Session>, user_id: i64) -> Result<Vec<Self>> {
+        let mut rows = scylla
+            .query_iter(
+                r#"SELECT id, ip_id, user_id, created_at, content
+                    FROM volksforo.ugc
+                    WHERE user_id = ?
+                ;"#,
+                (user_id,),
+            )
+            .await?
+            .into_typed::<Self>();
+        let mut revisions = Vec::new();
+
+        while let Some(row) = rows.next().await {
+            revisions.push(row?);
+        }
+
+        Ok(revisions)
+    }
+
     pub async fn fetch_many(
         scylla: Data<Session>,
         uuids: Vec<Uuid>,
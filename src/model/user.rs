@@ -1,9 +1,9 @@
-use super::Post;
+use super::group::REGISTERED_GROUP_ID;
+use super::{Group, UserName, UserSession};
+use crate::util::{normalize_email, normalize_username};
 use actix_web::web::Data;
 use anyhow::Result;
 use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
-use std::collections::HashMap;
-use tokio::task::JoinSet;
 use uuid::Uuid;
 
 #[derive(Debug, FromRow, Clone)]
@@ -16,7 +16,26 @@ pub struct User {
     pub password_cipher: String,
 }
 
+/// Why a user could not be created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimError {
+    UsernameTaken,
+    EmailTaken,
+}
+
+impl std::fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UsernameTaken => write!(f, "That username is already taken."),
+            Self::EmailTaken => write!(f, "That email address is already in use."),
+        }
+    }
+}
+
+impl std::error::Error for ClaimError {}
+
 impl User {
+    /// Registers a user. Fails with a [ClaimError] if the username or email belongs to someone else.
     pub async fn create(
         scylla: Data<Session>,
         username: String,
@@ -26,31 +45,176 @@ impl User {
         let id = crate::util::snowflake_id().await?;
         let user = Self {
             id: id.to_owned(),
-            username: username.to_owned(),
-            username_normal: username.to_lowercase(),
-            email,
+            username_normal: normalize_username(&username),
+            username,
+            email: email.map(|email| email.trim().to_owned()),
             password: crate::util::argon2_hash(&password)?,
-            password_cipher: "argon2".to_owned(),
+            password_cipher: crate::util::PASSWORD_CIPHER.to_owned(),
         };
-        user.insert(scylla).await?;
+
+        if !Self::claim_username(scylla.clone(), &user.username_normal, user.id).await? {
+            return Err(ClaimError::UsernameTaken.into());
+        }
+
+        if let Some(email) = &user.email {
+            let email_normal = normalize_email(email);
+            if !Self::claim_email(scylla.clone(), &email_normal, user.id).await? {
+                Self::release_username(scylla, &user.username_normal, user.id).await?;
+                return Err(ClaimError::EmailTaken.into());
+            }
+        }
+
+        let result = async {
+            user.insert(scylla.clone()).await?;
+            UserName::insert(scylla.clone(), user.id, &user.username).await?;
+            Group::assign_user(scylla.clone(), user.id, REGISTERED_GROUP_ID).await
+        }
+        .await;
+
+        // Undo what was written so the name and email can be registered again.
+        if let Err(err) = result {
+            if let Err(undo) = user.undo_create(scylla).await {
+                log::error!(
+                    "Failed to undo registration of user {}: {:?}",
+                    user.id,
+                    undo
+                );
+            }
+            return Err(err);
+        }
+
         Ok(user)
     }
 
-    pub async fn create_session(&self, scylla: Data<Session>) -> Result<Uuid> {
-        let uuid = Uuid::new_v4();
-        let timestamp = chrono::Utc::now().timestamp_millis();
+    /// Removes a user whose registration failed part way, releasing their claims.
+    async fn undo_create(&self, scylla: Data<Session>) -> Result<()> {
+        Group::remove_user(scylla.clone(), self.id).await?;
+        UserName::delete_history(scylla.clone(), self.id).await?;
+        Self::delete(scylla.clone(), self.id).await?;
+        if let Some(email) = &self.email {
+            Self::release_email(scylla.clone(), &normalize_email(email), self.id).await?;
+        }
+        Self::release_username(scylla, &self.username_normal, self.id).await
+    }
+
+    /// Changes a user's name, keeping the old one in their name history.
+    /// Fails with a [ClaimError] if the new name belongs to someone else.
+    pub async fn rename(&mut self, scylla: Data<Session>, username: String) -> Result<()> {
+        let username_normal = normalize_username(&username);
+        let claimed = username_normal != self.username_normal;
+
+        if claimed && !Self::claim_username(scylla.clone(), &username_normal, self.id).await? {
+            return Err(ClaimError::UsernameTaken.into());
+        }
 
         scylla
             .query(
-                r#"INSERT INTO volksforo.user_sessions
-                    (id, user_id, created_at, last_seen_at)
-                    VALUES (?, ?, ?, ?)
-                ;"#,
-                (&uuid, &self.id, timestamp, timestamp),
+                "UPDATE volksforo.users SET username = ?, username_normal = ? WHERE id = ?",
+                (&username, &username_normal, self.id),
             )
             .await?;
 
-        Ok(uuid)
+        if claimed {
+            Self::release_username(scylla.clone(), &self.username_normal, self.id).await?;
+        }
+
+        UserName::insert(scylla, self.id, &username).await?;
+        self.username = username;
+        self.username_normal = username_normal;
+        Ok(())
+    }
+
+    /// Claims a normalized username for a user. Returns false if someone else holds it.
+    pub async fn claim_username(
+        scylla: Data<Session>,
+        username_normal: &str,
+        user_id: i64,
+    ) -> Result<bool> {
+        Ok(crate::util::is_applied(
+            scylla
+                .query(
+                    r#"INSERT INTO volksforo.usernames_by_normal
+                        (username_normal, user_id)
+                        VALUES (?, ?)
+                        IF NOT EXISTS
+                    ;"#,
+                    (username_normal, user_id),
+                )
+                .await?,
+        ))
+    }
+
+    /// Releases a normalized username if the user still holds it.
+    pub async fn release_username(
+        scylla: Data<Session>,
+        username_normal: &str,
+        user_id: i64,
+    ) -> Result<()> {
+        scylla
+            .query(
+                "DELETE FROM volksforo.usernames_by_normal WHERE username_normal = ? IF user_id = ?",
+                (username_normal, user_id),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Claims a normalized email for a user. Returns false if someone else holds it.
+    pub async fn claim_email(
+        scylla: Data<Session>,
+        email_normal: &str,
+        user_id: i64,
+    ) -> Result<bool> {
+        Ok(crate::util::is_applied(
+            scylla
+                .query(
+                    r#"INSERT INTO volksforo.emails_by_normal
+                        (email_normal, user_id)
+                        VALUES (?, ?)
+                        IF NOT EXISTS
+                    ;"#,
+                    (email_normal, user_id),
+                )
+                .await?,
+        ))
+    }
+
+    /// Releases a normalized email if the user still holds it.
+    pub async fn release_email(
+        scylla: Data<Session>,
+        email_normal: &str,
+        user_id: i64,
+    ) -> Result<()> {
+        scylla
+            .query(
+                "DELETE FROM volksforo.emails_by_normal WHERE email_normal = ? IF user_id = ?",
+                (email_normal, user_id),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Hashes a new password with the current cipher and saves it.
+    pub async fn set_password(&mut self, scylla: Data<Session>, password: &str) -> Result<()> {
+        self.password = crate::util::argon2_hash(password)?;
+        self.password_cipher = crate::util::PASSWORD_CIPHER.to_owned();
+
+        scylla
+            .query(
+                "UPDATE volksforo.users SET password = ?, password_cipher = ? WHERE id = ?",
+                (&self.password, &self.password_cipher, self.id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_session(
+        &self,
+        scylla: Data<Session>,
+        user_agent: Option<&str>,
+    ) -> Result<Uuid> {
+        Ok(UserSession::create(scylla, self.id, user_agent).await?.id)
     }
 
     pub async fn fetch(scylla: Data<Session>, id: i64) -> Result<Option<Self>> {
@@ -75,83 +239,36 @@ impl User {
             .pop())
     }
 
+    /// Looks a user up by the normal form of their username.
     pub async fn fetch_by_username(
         scylla: Data<Session>,
-        username: String,
+        username_normal: String,
     ) -> Result<Option<Self>> {
-        Ok(scylla
+        let user_id = scylla
             .query(
-                "SELECT
-                    id,
-                    username,
-                    username_normal,
-                    email,
-                    password,
-                    password_cipher
-                FROM volksforo.users
-                WHERE username_normal = ?",
-                (username,),
+                "SELECT user_id FROM volksforo.usernames_by_normal WHERE username_normal = ?",
+                (username_normal,),
             )
             .await?
             .rows
             .unwrap_or_default()
-            .into_typed::<Self>()
-            .collect::<Result<Vec<Self>, FromRowError>>()?
-            .pop())
-    }
+            .into_typed::<(i64,)>()
+            .collect::<Result<Vec<(i64,)>, FromRowError>>()?
+            .pop();
 
-    pub async fn fetch_many(scylla: Data<Session>, ids: Vec<i64>) -> Result<HashMap<i64, Self>> {
-        let mut queries = JoinSet::new();
-        let mut models = HashMap::with_capacity(ids.len());
-
-        for id in ids {
-            let nscylla = scylla.to_owned();
-            queries.spawn(async move {
-                nscylla
-                    .query(
-                        r#"SELECT
-                            id,
-                            username,
-                            username_normal,
-                            email,
-                            password,
-                            password_cipher
-                        FROM volksforo.users
-                        WHERE id = ?
-                        LIMIT 1
-                        ;"#,
-                        (id,),
-                    )
-                    .await
-            });
-        }
-
-        while let Some(result) = queries.join_next().await {
-            if let Some(rows) = result??.rows {
-                for row in rows.into_typed::<Self>() {
-                    let model = row?;
-                    models.insert(model.id, model);
-                }
-            }
+        match user_id {
+            Some((user_id,)) => Self::fetch(scylla, user_id).await,
+            None => Ok(None),
         }
-
-        Ok(models)
     }
 
-    /// Returns a map of author ids to user data.
-    pub async fn fetch_many_post_authors(
-        scylla: Data<Session>,
-        posts: &[Post],
-    ) -> Result<HashMap<i64, Self>> {
-        Self::fetch_many(
-            scylla.clone(),
-            posts
-                .iter()
-                .map(|x| x.user_id)
-                .flatten()
-                .collect::<Vec<i64>>(),
-        )
-        .await
+    /// Removes the user row, and with it their email and password.
+    /// Claims, sessions, groups and names are removed separately. See [crate::account_deletion].
+    pub async fn delete(scylla: Data<Session>, id: i64) -> Result<()> {
+        scylla
+            .query("DELETE FROM volksforo.users WHERE id = ?", (id,))
+            .await?;
+        Ok(())
     }
 
     pub async fn insert(&self, scylla: Data<Session>) -> Result<()> {
@@ -0,0 +1,118 @@
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::Duration;
+use futures_util::StreamExt;
+use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
+
+/// Record of an account deletion. Unfinished deletions are resumed after a restart.
+#[derive(Debug, FromRow, Clone)]
+pub struct UserDeletion {
+    pub user_id: i64,
+    /// None if the user deleted their own account.
+    pub requested_by: Option<i64>,
+    pub requested_at: Duration,
+    /// If set, the user's names are forgotten and their content is shown as by "Deleted member #n".
+    pub erase_name: bool,
+    pub finished_at: Option<Duration>,
+}
+
+impl UserDeletion {
+    pub fn new(user_id: i64, requested_by: Option<i64>, erase_name: bool) -> Self {
+        Self {
+            user_id,
+            requested_by,
+            requested_at: Duration::milliseconds(chrono::Utc::now().timestamp_millis()),
+            erase_name,
+            finished_at: None,
+        }
+    }
+
+    /// Inserts the record unless the user is already being deleted. Returns true if this call created it.
+    pub async fn insert_if_absent(&self, scylla: Data<Session>) -> Result<bool> {
+        let result = scylla
+            .query(
+                r#"INSERT INTO volksforo.user_deletions (
+                    user_id,
+                    requested_by,
+                    requested_at,
+                    erase_name
+                )
+                VALUES (?, ?, ?, ?)
+                IF NOT EXISTS
+                ;"#,
+                (
+                    self.user_id,
+                    self.requested_by,
+                    self.requested_at.num_milliseconds(),
+                    self.erase_name,
+                ),
+            )
+            .await?;
+
+        Ok(crate::util::is_applied(result))
+    }
+
+    /// Marks the deletion as complete.
+    pub async fn finish(&mut self, scylla: Data<Session>) -> Result<()> {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        scylla
+            .query(
+                "UPDATE volksforo.user_deletions SET finished_at = ? WHERE user_id = ?",
+                (now.num_milliseconds(), self.user_id),
+            )
+            .await?;
+
+        self.finished_at = Some(now);
+        Ok(())
+    }
+
+    pub async fn fetch(scylla: Data<Session>, user_id: i64) -> Result<Option<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT
+                    user_id,
+                    requested_by,
+                    requested_at,
+                    erase_name,
+                    finished_at
+                FROM volksforo.user_deletions
+                WHERE user_id = ?
+                ;"#,
+                (user_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?
+            .pop())
+    }
+
+    /// Returns every deletion which has not finished.
+    pub async fn fetch_unfinished(scylla: Data<Session>) -> Result<Vec<Self>> {
+        let mut rows = scylla
+            .query_iter(
+                r#"SELECT
+                    user_id,
+                    requested_by,
+                    requested_at,
+                    erase_name,
+                    finished_at
+                FROM volksforo.user_deletions
+                ;"#,
+                (),
+            )
+            .await?
+            .into_typed::<Self>();
+        let mut deletions = Vec::new();
+
+        while let Some(row) = rows.next().await {
+            let deletion = row?;
+            if deletion.finished_at.is_none() {
+                deletions.push(deletion);
+            }
+        }
+
+        Ok(deletions)
+    }
+}
@@ -0,0 +1,180 @@
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::Duration;
+use futures_util::StreamExt;
+use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
+use uuid::Uuid;
+
+/// Minutes an archive may take to build. Unfinished exports older than this were abandoned,
+/// usually by a restart, and are removed so the user can ask again.
+const BUILD_TIMEOUT: i64 = 30;
+
+/// A user's request for an archive of their personal data.
+#[derive(Debug, FromRow, Clone)]
+pub struct UserExport {
+    pub user_id: i64,
+    pub id: Uuid,
+    pub requested_at: Duration,
+    /// None while the archive is being built.
+    pub finished_at: Option<Duration>,
+    pub expires_at: Duration,
+}
+
+impl UserExport {
+    pub fn new(user_id: i64, lifetime: Duration) -> Self {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        Self {
+            user_id,
+            id: Uuid::new_v4(),
+            requested_at: now,
+            finished_at: None,
+            expires_at: now + lifetime,
+        }
+    }
+
+    /// Where the archive is kept in storage.
+    pub fn storage_key(&self) -> String {
+        format!("exports/{}.zip", self.id)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Duration::milliseconds(chrono::Utc::now().timestamp_millis()) >= self.expires_at
+    }
+
+    /// True if the archive was never finished and is no longer being built.
+    pub fn is_stale(&self) -> bool {
+        self.finished_at.is_none()
+            && Duration::milliseconds(chrono::Utc::now().timestamp_millis())
+                >= self.requested_at + Duration::minutes(BUILD_TIMEOUT)
+    }
+
+    pub async fn insert(&self, scylla: Data<Session>) -> Result<()> {
+        scylla
+            .query(
+                r#"INSERT INTO volksforo.user_exports
+                    (user_id, id, requested_at, expires_at)
+                    VALUES (?, ?, ?, ?)
+                ;"#,
+                (
+                    self.user_id,
+                    self.id,
+                    self.requested_at.num_milliseconds(),
+                    self.expires_at.num_milliseconds(),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks the archive as ready to download.
+    /// Returns false if the request was removed while the archive was being built.
+    pub async fn finish(&mut self, scylla: Data<Session>) -> Result<bool> {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        let result = scylla
+            .query(
+                r#"UPDATE volksforo.user_exports
+                    SET finished_at = ?
+                    WHERE user_id = ? AND id = ?
+                    IF EXISTS
+                ;"#,
+                (now.num_milliseconds(), self.user_id, self.id),
+            )
+            .await?;
+
+        self.finished_at = Some(now);
+        Ok(crate::util::is_applied(result))
+    }
+
+    pub async fn delete(&self, scylla: Data<Session>) -> Result<()> {
+        scylla
+            .query(
+                "DELETE FROM volksforo.user_exports WHERE user_id = ? AND id = ?",
+                (self.user_id, self.id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn fetch(scylla: Data<Session>, user_id: i64, id: Uuid) -> Result<Option<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT
+                    user_id,
+                    id,
+                    requested_at,
+                    finished_at,
+                    expires_at
+                FROM volksforo.user_exports
+                WHERE user_id = ? AND id = ?
+                ;"#,
+                (user_id, id),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?
+            .pop())
+    }
+
+    /// Returns a user's unexpired exports which are ready or still being built, newest first.
+    pub async fn fetch_for_user(scylla: Data<Session>, user_id: i64) -> Result<Vec<Self>> {
+        let mut exports = Self::fetch_all_for_user(scylla, user_id).await?;
+
+        exports.retain(|export| !export.is_expired() && !export.is_stale());
+        exports.sort_by_key(|export| std::cmp::Reverse(export.requested_at));
+        Ok(exports)
+    }
+
+    /// Returns every export a user has, including expired and abandoned ones.
+    pub async fn fetch_all_for_user(scylla: Data<Session>, user_id: i64) -> Result<Vec<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT
+                    user_id,
+                    id,
+                    requested_at,
+                    finished_at,
+                    expires_at
+                FROM volksforo.user_exports
+                WHERE user_id = ?
+                ;"#,
+                (user_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?)
+    }
+
+    /// Returns every export which has expired or was abandoned while being built.
+    pub async fn fetch_expired(scylla: Data<Session>) -> Result<Vec<Self>> {
+        let mut rows = scylla
+            .query_iter(
+                r#"SELECT
+                    user_id,
+                    id,
+                    requested_at,
+                    finished_at,
+                    expires_at
+                FROM volksforo.user_exports
+                ;"#,
+                (),
+            )
+            .await?
+            .into_typed::<Self>();
+        let mut exports = Vec::new();
+
+        while let Some(row) = rows.next().await {
+            let export = row?;
+            if export.is_expired() || export.is_stale() {
+                exports.push(export);
+            }
+        }
+
+        Ok(exports)
+    }
+}
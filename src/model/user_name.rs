@@ -0,0 +1,122 @@
+use super::Post;
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::Duration;
+use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
+use std::collections::HashMap;
+use tokio::task::JoinSet;
+
+/// A name a user went by from `created_at` until their next rename.
+/// Names outlive the user row, so content by deleted users still shows who wrote it.
+#[derive(Debug, FromRow, Clone)]
+pub struct UserName {
+    pub user_id: i64,
+    pub created_at: Duration,
+    pub username: String,
+}
+
+impl UserName {
+    /// Records a name as the user's current one.
+    pub async fn insert(scylla: Data<Session>, user_id: i64, username: &str) -> Result<Self> {
+        let name = Self {
+            user_id,
+            created_at: Duration::milliseconds(chrono::Utc::now().timestamp_millis()),
+            username: username.to_owned(),
+        };
+
+        scylla
+            .query(
+                r#"INSERT INTO volksforo.user_name
+                    (user_id, created_at, username)
+                    VALUES (?, ?, ?)
+                ;"#,
+                (
+                    name.user_id,
+                    name.created_at.num_milliseconds(),
+                    &name.username,
+                ),
+            )
+            .await?;
+
+        Ok(name)
+    }
+
+    /// Forgets every name a user went by.
+    pub async fn delete_history(scylla: Data<Session>, user_id: i64) -> Result<()> {
+        scylla
+            .query(
+                "DELETE FROM volksforo.user_name WHERE user_id = ?",
+                (user_id,),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns every name a user went by, newest first.
+    pub async fn fetch_history(scylla: Data<Session>, user_id: i64) -> Result<Vec<Self>> {
+        Ok(scylla
+            .query(
+                r#"SELECT
+                    user_id,
+                    created_at,
+                    username
+                FROM volksforo.user_name
+                WHERE user_id = ?
+                ;"#,
+                (user_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?)
+    }
+
+    /// Returns a map of user ids to their current names. Users with no name are absent.
+    pub async fn fetch_many(scylla: Data<Session>, ids: Vec<i64>) -> Result<HashMap<i64, Self>> {
+        let mut queries = JoinSet::new();
+        let mut models = HashMap::with_capacity(ids.len());
+
+        for id in ids {
+            let nscylla = scylla.to_owned();
+            queries.spawn(async move {
+                nscylla
+                    .query(
+                        r#"SELECT
+                            user_id,
+                            created_at,
+                            username
+                        FROM volksforo.user_name
+                        WHERE user_id = ?
+                        LIMIT 1
+                        ;"#,
+                        (id,),
+                    )
+                    .await
+            });
+        }
+
+        while let Some(result) = queries.join_next().await {
+            if let Some(rows) = result??.rows {
+                for row in rows.into_typed::<Self>() {
+                    let model = row?;
+                    models.insert(model.user_id, model);
+                }
+            }
+        }
+
+        Ok(models)
+    }
+
+    /// Returns a map of author ids to their current names.
+    pub async fn fetch_many_post_authors(
+        scylla: Data<Session>,
+        posts: &[Post],
+    ) -> Result<HashMap<i64, Self>> {
+        let mut ids: Vec<i64> = posts.iter().filter_map(|post| post.user_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Self::fetch_many(scylla, ids).await
+    }
+}
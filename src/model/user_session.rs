@@ -1,32 +1,161 @@
 use actix_web::web::Data;
 use anyhow::Result;
 use chrono::Duration;
+use once_cell::sync::Lazy;
 use scylla::{cql_to_rust::FromRowError, FromRow, IntoTypedRows, Session};
 use uuid::Uuid;
 
+/// Minutes a session lasts after login, however active it is.
+static SESSION_TIME: Lazy<Duration> = Lazy::new(|| {
+    Duration::minutes(
+        std::env::var("VF_SESSION_TIME")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(1440),
+    )
+});
+
+/// Minutes a session lasts without a request.
+static SESSION_IDLE_TIME: Lazy<Duration> = Lazy::new(|| {
+    Duration::minutes(
+        std::env::var("VF_SESSION_IDLE_TIME")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(120),
+    )
+});
+
+/// Least time between bumps of a session's last_seen_at. A tenth of the idle time, at most a minute.
+static BUMP_INTERVAL: Lazy<Duration> =
+    Lazy::new(|| std::cmp::min(Duration::minutes(1), *SESSION_IDLE_TIME / 10));
+
+/// Longest user agent kept with a session.
+const USER_AGENT_LENGTH: usize = 255;
+
 #[derive(Debug, FromRow, Clone)]
 pub struct UserSession {
     pub id: Uuid,
     pub user_id: i64,
     pub created_at: Duration,
     pub last_seen_at: Duration,
+    pub user_agent: Option<String>,
 }
 
 impl UserSession {
-    // Increments the last_seen_at timestamp of a user session.
-    pub async fn bump_last_seen_at(scylla: Data<Session>, uuid: &Uuid) -> Result<()> {
-        let timestamp = chrono::Utc::now().timestamp_millis();
+    /// Starts a new session for a user.
+    pub async fn create(
+        scylla: Data<Session>,
+        user_id: i64,
+        user_agent: Option<&str>,
+    ) -> Result<Self> {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        let session = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            created_at: now,
+            last_seen_at: now,
+            user_agent: user_agent.map(|agent| agent.chars().take(USER_AGENT_LENGTH).collect()),
+        };
+        session.insert(scylla).await?;
+        Ok(session)
+    }
+
+    /// Returns true if the session has outlived either its absolute or idle expiry.
+    pub fn is_expired(&self) -> bool {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        now - self.created_at >= *SESSION_TIME || now - self.last_seen_at >= *SESSION_IDLE_TIME
+    }
+
+    /// Returns true if last_seen_at is old enough to be worth writing again.
+    pub fn needs_bump(&self) -> bool {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        now - self.last_seen_at >= *BUMP_INTERVAL
+    }
+
+    /// Returns how long until the session reaches its absolute expiry, in seconds.
+    /// Idle sessions are caught by [Self::is_expired] and deleted when next used.
+    fn ttl(&self) -> i32 {
+        let now = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        let remaining = self.created_at + *SESSION_TIME - now;
+        // A TTL of zero would keep the row forever.
+        remaining.num_seconds().clamp(1, i32::MAX as i64) as i32
+    }
+
+    /// Every column shares one TTL so the whole row expires with the session.
+    /// Every write to a session is a lightweight transaction, as bumps race with deletes.
+    async fn insert(&self, scylla: Data<Session>) -> Result<()> {
+        scylla
+            .query(
+                r#"INSERT INTO volksforo.user_sessions
+                    (id, user_id, created_at, last_seen_at, user_agent)
+                    VALUES (?, ?, ?, ?, ?)
+                    IF NOT EXISTS
+                    USING TTL ?
+                ;"#,
+                (
+                    self.id,
+                    self.user_id,
+                    self.created_at.num_milliseconds(),
+                    self.last_seen_at.num_milliseconds(),
+                    &self.user_agent,
+                    self.ttl(),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Updates the last_seen_at timestamp of a user session, extending its idle expiry.
+    /// Bumps race with logouts, so a session which has since been deleted is left deleted.
+    pub async fn bump_last_seen_at(mut self, scylla: Data<Session>) -> Result<()> {
+        self.last_seen_at = Duration::milliseconds(chrono::Utc::now().timestamp_millis());
+        scylla
+            .query(
+                r#"UPDATE volksforo.user_sessions USING TTL ?
+                    SET last_seen_at = ?
+                    WHERE id = ?
+                    IF EXISTS
+                ;"#,
+                (self.ttl(), self.last_seen_at.num_milliseconds(), self.id),
+            )
+            .await?;
+
+        Ok(())
+    }
 
+    /// Ends a session.
+    pub async fn delete(scylla: Data<Session>, uuid: &Uuid) -> Result<()> {
         scylla
             .query(
-                "UPDATE volksforo.user_sessions SET last_seen_at = ? WHERE id = ?;",
-                (&timestamp, uuid),
+                "DELETE FROM volksforo.user_sessions WHERE id = ? IF EXISTS;",
+                (uuid,),
             )
             .await?;
 
         Ok(())
     }
 
+    /// Ends every session of a user, including expired ones.
+    pub async fn delete_for_user(scylla: Data<Session>, user_id: i64) -> Result<()> {
+        let ids = scylla
+            .query(
+                "SELECT id FROM volksforo.user_sessions WHERE user_id = ?;",
+                (user_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(Uuid,)>()
+            .collect::<Result<Vec<(Uuid,)>, FromRowError>>()?;
+
+        for (id,) in ids {
+            Self::delete(scylla.clone(), &id).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn fetch(scylla: Data<Session>, uuid: &Uuid) -> Result<Option<Self>> {
         Ok(scylla
             .query(
@@ -34,7 +163,8 @@ impl UserSession {
                         id,
                         user_id,
                         created_at,
-                        last_seen_at
+                        last_seen_at,
+                        user_agent
                     FROM volksforo.user_sessions
                     WHERE id = ?
                 ;"#,
@@ -47,4 +177,30 @@ impl UserSession {
             .collect::<Result<Vec<Self>, FromRowError>>()?
             .pop())
     }
+
+    /// Returns a user's unexpired sessions, most recently seen first.
+    pub async fn fetch_for_user(scylla: Data<Session>, user_id: i64) -> Result<Vec<Self>> {
+        let mut sessions = scylla
+            .query(
+                r#"SELECT
+                        id,
+                        user_id,
+                        created_at,
+                        last_seen_at,
+                        user_agent
+                    FROM volksforo.user_sessions
+                    WHERE user_id = ?
+                ;"#,
+                (user_id,),
+            )
+            .await?
+            .rows
+            .unwrap_or_default()
+            .into_typed::<Self>()
+            .collect::<Result<Vec<Self>, FromRowError>>()?;
+
+        sessions.retain(|session| !session.is_expired());
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+        Ok(sessions)
+    }
 }
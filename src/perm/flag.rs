@@ -1,5 +1,5 @@
 /// Value set for a single permission.
-/// Compatible with sea_orm enum type.
+/// Stored as a tinyint in `permission_values`.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Flag {
     /// Grants permission
@@ -11,3 +11,17 @@ pub enum Flag {
     /// Never permitted, cannot be re-permitted
     NEVER = -2,
 }
+
+impl TryFrom<i8> for Flag {
+    type Error = i8;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::YES),
+            0 => Ok(Self::DEFAULT),
+            -1 => Ok(Self::NO),
+            -2 => Ok(Self::NEVER),
+            _ => Err(value),
+        }
+    }
+}
@@ -27,23 +27,34 @@ pub const PERM_LIMIT: u32 = u64::BITS;
 /// Total maximum number of permissions defined as GROUP_LIMIT*PERM_LIMIT
 pub const MAX_PERMS: u32 = GROUP_LIMIT * PERM_LIMIT;
 
-use crate::middleware::ClientCtx;
+use crate::middleware::Context;
+use actix_web::web::Data;
+use anyhow::Result;
+use collection_values::CollectionValues;
 use dashmap::DashMap;
+use scylla::{IntoTypedRows, Session};
+use std::collections::HashMap;
+
+/// Maximum depth walked when resolving a resource's ancestry.
+const RESOURCE_DEPTH_LIMIT: usize = 32;
 
 #[derive(Clone, Debug, Default)]
 pub struct PermissionData {
     /// Threadsafe Data Structure
     collection: collection::Collection,
-    /// (Group, User) -> CollectionValues Relationship
-    collection_values: DashMap<(i32, i32), collection_values::CollectionValues>,
+    /// (Resource, Group, User) -> CollectionValues Relationship
+    /// Resource 0 holds the global values every resource inherits.
+    collection_values: DashMap<(i64, i32, i64), CollectionValues>,
+    /// Resource ID -> Resource (parent and children)
+    resources: DashMap<i64, resource::Resource>,
 }
 
 impl PermissionData {
     /// Accepts Client/Guest and Permission Name for permission check.
-    pub fn can(&self, client: &ClientCtx, permission: &str) -> bool {
+    pub fn can(&self, context: &Context, permission: &str) -> bool {
         // Look up the permissions's indices by name.
         if let Some(pindices) = self.collection.dictionary.get(permission) {
-            self.can_by_indices(client, &pindices)
+            self.can_by_indices(context, &pindices)
         } else {
             log::warn!(
                 "Bad permission check on name '{:?}', which is not present in our dictionary.",
@@ -54,10 +65,10 @@ impl PermissionData {
     }
 
     /// Accepts Client/Guest and Permission ID for permission check.
-    pub fn can_by_id(&self, client: &ClientCtx, permission_id: i32) -> bool {
+    pub fn can_by_id(&self, context: &Context, permission_id: i32) -> bool {
         // Look up the permissions's indices by id.
         if let Some(pindices) = self.collection.lookup.get(&permission_id) {
-            self.can_by_indices(client, &pindices)
+            self.can_by_indices(context, &pindices)
         } else {
             log::warn!(
                 "Bad permission check on id {:?}, which is not present in our dictionary.",
@@ -68,27 +79,116 @@ impl PermissionData {
     }
 
     /// Accepts Client/Guest and specific permission indices for permission check.
-    pub fn can_by_indices(&self, client: &ClientCtx, indices: &(u8, u8)) -> bool {
-        let groups = client.get_groups();
-        let values = match client.get_id() {
-            Some(id) => {
-                let group_values = self.join_for_groups(&groups);
-                let user_values = self.join_for_user(id);
-                group_values.join(&user_values)
-            }
-            None => self.join_for_groups(&groups),
-        };
+    pub fn can_by_indices(&self, context: &Context, indices: &(u8, u8)) -> bool {
+        self.can_by_indices_in(context, indices, 0)
+    }
+
+    /// Accepts Client/Guest, Permission Name, and a Resource for permission check.
+    /// Values set on the resource and its ancestors are stacked over the global values.
+    pub fn can_in(&self, context: &Context, permission: &str, resource_id: i64) -> bool {
+        if let Some(pindices) = self.collection.dictionary.get(permission) {
+            self.can_by_indices_in(context, &pindices, resource_id)
+        } else {
+            log::warn!(
+                "Bad permission check on name '{:?}', which is not present in our dictionary.",
+                permission
+            );
+            false
+        }
+    }
+
+    /// Accepts Client/Guest, specific permission indices, and a Resource for permission check.
+    pub fn can_by_indices_in(
+        &self,
+        context: &Context,
+        indices: &(u8, u8),
+        resource_id: i64,
+    ) -> bool {
+        let values = self.values_in(
+            &context.groups,
+            context.visitor.user.as_ref().map(|u| u.id),
+            resource_id,
+        );
 
         let mask = mask::Mask::from(values);
         mask.can(indices.0 as usize, indices.1 as i32)
     }
 
-    pub fn join_for_groups(&self, groups: &Vec<i32>) -> collection_values::CollectionValues {
-        use collection_values::CollectionValues;
+    /// Accepts another user's groups and id, and a Permission Name, for checking what they may do.
+    pub fn user_can(&self, groups: &[i32], user_id: i64, permission: &str) -> bool {
+        if let Some(pindices) = self.collection.dictionary.get(permission) {
+            let mask = mask::Mask::from(self.values_in(groups, Some(user_id), 0));
+            mask.can(pindices.0 as usize, pindices.1 as i32)
+        } else {
+            log::warn!(
+                "Bad permission check on name '{:?}', which is not present in our dictionary.",
+                permission
+            );
+            false
+        }
+    }
+
+    /// Resolves the final values for a client on a resource.
+    /// Walks from the global values down through each ancestor, stacking each level.
+    /// A NO on a child overrides a YES on its parent. NEVER always wins.
+    pub fn values_in(
+        &self,
+        groups: &[i32],
+        user_id: Option<i64>,
+        resource_id: i64,
+    ) -> CollectionValues {
+        let mut values = self.join_for_client(0, groups, user_id);
+
+        for id in self.get_ancestry(resource_id) {
+            values = self.join_for_client(id, groups, user_id).stack(&values);
+        }
+
+        values
+    }
+
+    /// Returns resource ids from the root down to and including the resource.
+    pub fn get_ancestry(&self, resource_id: i64) -> Vec<i64> {
+        let mut ancestry = Vec::new();
+        let mut next = Some(resource_id);
+
+        while let Some(id) = next {
+            if id == 0 || ancestry.contains(&id) {
+                break;
+            }
+
+            if ancestry.len() >= RESOURCE_DEPTH_LIMIT {
+                log::warn!("Resource {} exceeds the ancestry depth limit.", resource_id);
+                break;
+            }
+
+            ancestry.push(id);
+            next = self.resources.get(&id).and_then(|r| r.parent);
+        }
+
+        ancestry.reverse();
+        ancestry
+    }
+
+    /// Joins group and user values set directly on one resource.
+    pub fn join_for_client(
+        &self,
+        resource_id: i64,
+        groups: &[i32],
+        user_id: Option<i64>,
+    ) -> CollectionValues {
+        let group_values = self.join_for_groups(resource_id, groups);
+
+        match user_id {
+            Some(id) => group_values.join(&self.join_for_user(resource_id, id)),
+            None => group_values,
+        }
+    }
+
+    pub fn join_for_groups(&self, resource_id: i64, groups: &[i32]) -> CollectionValues {
         let mut return_values = CollectionValues::default();
 
         for group in groups {
-            let val_key = (group.to_owned(), 0);
+            let val_key = (resource_id, group.to_owned(), 0);
 
             if let Some(group_values) = self.collection_values.get(&val_key) {
                 return_values = return_values.join(&group_values);
@@ -98,10 +198,9 @@ impl PermissionData {
         return_values
     }
 
-    pub fn join_for_user(&self, id: i32) -> collection_values::CollectionValues {
-        use collection_values::CollectionValues;
+    pub fn join_for_user(&self, resource_id: i64, id: i64) -> CollectionValues {
         let mut return_values = CollectionValues::default();
-        let val_key = (0, id);
+        let val_key = (resource_id, 0, id);
 
         if let Some(group_values) = self.collection_values.get(&val_key) {
             return_values = return_values.join(&group_values);
@@ -111,80 +210,171 @@ impl PermissionData {
     }
 }
 
-pub async fn new() -> Result<PermissionData, sea_orm::error::DbErr> {
-    use crate::db::get_db_pool;
-    use crate::orm::permission_collections;
-    use crate::orm::permission_values;
-    use crate::orm::permissions;
-    use collection_values::CollectionValues;
-    use sea_orm::entity::*;
-
+/// Builds the permission structure and values from Scylla.
+pub async fn new(scylla: Data<Session>) -> Result<PermissionData> {
     // Build structure tree
     let mut col = collection::Collection::default();
 
+    let (categories, items, collections, values, nodes) = tokio::try_join!(
+        scylla.query("SELECT id FROM volksforo.permission_categories", &[]),
+        scylla.query(
+            "SELECT id, category_id, label FROM volksforo.permission_items",
+            &[]
+        ),
+        scylla.query(
+            "SELECT id, group_id, user_id, node_id FROM volksforo.permission_collections",
+            &[]
+        ),
+        scylla.query(
+            "SELECT collection_id, item_id, value FROM volksforo.permission_values",
+            &[]
+        ),
+        scylla.query("SELECT id, parent_id FROM volksforo.nodes", &[]),
+    )?;
+
     // Import permissions
-    let items = permissions::Entity::find().all(get_db_pool()).await?;
+    let mut items = items
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i32, i32, String)>()
+        .collect::<Result<Vec<_>, _>>()?;
+    items.sort_unstable_by_key(|(id, _, _)| *id);
 
-    // Pull unique category id list from permissions.
-    let mut ucid: Vec<i32> = items.iter().map(|i| i.category_id).collect();
+    // Pull unique category id list.
+    let mut ucid: Vec<i32> = categories
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i32,)>()
+        .map(|row| row.map(|(id,)| id))
+        .collect::<Result<Vec<_>, _>>()?;
     ucid.sort_unstable();
     ucid.dedup();
 
+    if ucid.len() > GROUP_LIMIT as usize {
+        log::error!(
+            "{} permission categories exist but only {} can be loaded.",
+            ucid.len(),
+            GROUP_LIMIT
+        );
+        ucid.truncate(GROUP_LIMIT as usize);
+    }
+
     // Add categories to collection and order them.
     for (i, cid) in ucid.iter().enumerate() {
         col.categories[i].id = *cid;
         col.categories[i].position = i as u8;
 
         // Add permissions belonging to this category.
-        for item in items.iter() {
-            if *cid == item.category_id {
-                match col.categories[i].add_item(item.id, &item.label) {
+        for (item_id, category_id, label) in items.iter() {
+            if cid == category_id {
+                match col.categories[i].add_item(*item_id, label) {
                     Ok(item) => {
                         col.dictionary
-                            .insert(item.label.to_owned(), (i as u8, item.position as u8));
-                        col.lookup.insert(item.id, (i as u8, item.position as u8));
+                            .insert(item.label.to_owned(), (i as u8, item.position));
+                        col.lookup.insert(item.id, (i as u8, item.position));
                     }
                     Err(_) => {
-                        log::error!("Category overflow adding {:?}", item);
+                        log::error!("Category overflow adding permission {:?}", label);
                     }
                 }
             }
         }
     }
 
+    // Import resource tree
+    let resources: DashMap<i64, resource::Resource> = DashMap::new();
+    for row in nodes
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i64, Option<i64>)>()
+    {
+        let (id, parent) = row?;
+        let parent = parent.filter(|p| *p != 0);
+
+        {
+            let mut resource = resources.entry(id).or_default();
+            resource.id = id;
+            resource.parent = parent;
+        }
+
+        if let Some(parent) = parent {
+            let mut resource = resources.entry(parent).or_default();
+            resource.id = parent;
+            resource.children.get_or_insert_with(Vec::new).push(id);
+        }
+    }
+
     // Import data
-    let vals: DashMap<(i32, i32), CollectionValues> = Default::default();
-    let perm_collections = permission_collections::Entity::find()
-        .find_with_related(permission_values::Entity)
-        .all(get_db_pool())
-        .await?;
-
-    // convert ORM data into permission system structs
-    // loop through the collection-<values relations
-    for (perm_collection, pvs) in perm_collections.iter() {
-        // Create collection values record to set flags on
-        let mut cv = CollectionValues::default();
-
-        // loop through the values
-        for pv in pvs.iter() {
-            // Look up the permissions's indices by id.
-            if let Some(pindices) = col.lookup.get(&pv.permission_id) {
-                // Assign each flag to the CollectionValues.
-                cv.set_flag(pindices.0, pindices.1, pv.value);
-            } else {
+    // Collection ID -> (Resource, Group, User)
+    let keys: HashMap<i32, (i64, i32, i64)> = collections
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i32, Option<i32>, Option<i64>, Option<i64>)>()
+        .map(|row| {
+            row.map(|(id, group_id, user_id, node_id)| {
+                (
+                    id,
+                    (
+                        node_id.unwrap_or(0),
+                        group_id.unwrap_or(0),
+                        user_id.unwrap_or(0),
+                    ),
+                )
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let mut collection_values: HashMap<i32, CollectionValues> = HashMap::new();
+
+    // Convert rows into permission system structs.
+    for row in values
+        .rows
+        .unwrap_or_default()
+        .into_typed::<(i32, i32, i8)>()
+    {
+        let (collection_id, item_id, value) = row?;
+
+        let flag = match Flag::try_from(value) {
+            Ok(flag) => flag,
+            Err(_) => {
                 log::error!(
-                    "Failed to lookup indices for permission_values {:?},{:?}",
-                    pv.collection_id,
-                    pv.permission_id
+                    "Invalid flag {} for permission_values {:?},{:?}",
+                    value,
+                    collection_id,
+                    item_id
                 );
+                continue;
             }
+        };
+
+        // Look up the permissions's indices by id.
+        if let Some(pindices) = col.lookup.get(&item_id) {
+            // Assign each flag to the CollectionValues.
+            collection_values
+                .entry(collection_id)
+                .or_default()
+                .set_flag(pindices.0, pindices.1, flag);
+        } else {
+            log::error!(
+                "Failed to lookup indices for permission_values {:?},{:?}",
+                collection_id,
+                item_id
+            );
         }
+    }
 
-        // Resolve (group,user) tuple key
-        let val_key: (i32, i32) = (
-            perm_collection.group_id.unwrap_or(0),
-            perm_collection.user_id.unwrap_or(0),
-        );
+    let vals: DashMap<(i64, i32, i64), CollectionValues> = Default::default();
+    for (collection_id, cv) in collection_values {
+        // Resolve (resource,group,user) tuple key
+        let val_key = match keys.get(&collection_id) {
+            Some(key) => *key,
+            None => {
+                log::error!(
+                    "permission_values reference missing collection {:?}",
+                    collection_id
+                );
+                continue;
+            }
+        };
 
         if vals.contains_key(&val_key) {
             // Join permission with same key.
@@ -198,5 +388,6 @@ pub async fn new() -> Result<PermissionData, sea_orm::error::DbErr> {
     Ok(PermissionData {
         collection: col,
         collection_values: vals,
+        resources,
     })
 }
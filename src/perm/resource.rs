@@ -1,7 +1,8 @@
 /// Organiztion struct.
 /// Relational data used for finalizing permission masks in real use.
+#[derive(Clone, Debug, Default)]
 pub struct Resource {
-    pub id: i32,
-    pub parent: Option<i32>,
-    pub children: Option<Vec<i32>>,
+    pub id: i64,
+    pub parent: Option<i64>,
+    pub children: Option<Vec<i64>>,
 }
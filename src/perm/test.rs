@@ -261,3 +261,69 @@ fn test_values_stack_negatives() {
     assert_eq!(group3.no, 0b00010u64);
     assert_eq!(group3.never, 0b01001u64);
 }
+
+#[test]
+fn test_values_in_resource_ancestry() {
+    use super::collection_values::CollectionValues;
+    use super::flag::Flag;
+    use super::mask::Mask;
+    use super::resource::Resource;
+    use super::PermissionData;
+
+    let data = PermissionData::default();
+
+    // 1 -> 2 -> 3
+    for (id, parent) in [(1, None), (2, Some(1)), (3, Some(2))] {
+        data.resources.insert(
+            id,
+            Resource {
+                id,
+                parent,
+                children: None,
+            },
+        );
+    }
+
+    // Global: group 1 may view (0) and reply (1).
+    let mut global = CollectionValues::default();
+    global.set_flag(0, 0, Flag::YES);
+    global.set_flag(0, 1, Flag::YES);
+    data.collection_values.insert((0, 1, 0), global);
+
+    // Resource 2: group 1 may not reply.
+    let mut child = CollectionValues::default();
+    child.set_flag(0, 1, Flag::NO);
+    data.collection_values.insert((2, 1, 0), child);
+
+    // Resource 3: group 1 may reply again, and may view.
+    let mut grandchild = CollectionValues::default();
+    grandchild.set_flag(0, 0, Flag::YES);
+    grandchild.set_flag(0, 1, Flag::YES);
+    data.collection_values.insert((3, 1, 0), grandchild);
+
+    let mut never = CollectionValues::default();
+    never.set_flag(0, 0, Flag::NEVER);
+    data.collection_values.insert((2, 2, 0), never);
+
+    assert_eq!(data.get_ancestry(3), vec![1, 2, 3]);
+    assert_eq!(data.get_ancestry(4), vec![4]);
+
+    let mask = Mask::from(data.values_in(&[1], None, 1));
+    assert!(mask.can(0, 0));
+    assert!(mask.can(0, 1));
+
+    // NO on a child overrides a YES on the parent.
+    let mask = Mask::from(data.values_in(&[1], None, 2));
+    assert!(mask.can(0, 0));
+    assert!(!mask.can(0, 1));
+
+    // YES on a grandchild overrides a NO above it.
+    let mask = Mask::from(data.values_in(&[1], None, 3));
+    assert!(mask.can(0, 0));
+    assert!(mask.can(0, 1));
+
+    // NEVER set above always wins.
+    let mask = Mask::from(data.values_in(&[1, 2], None, 3));
+    assert!(!mask.can(0, 0));
+    assert!(mask.can(0, 1));
+}
@@ -0,0 +1,206 @@
+//! Counter reconciliation.
+//! Scylla counters cannot be set, only adjusted, and drift whenever an increment is lost or doubled.
+//! This recounts what can be recounted from the tables counters summarize and applies the difference.
+//! Each counter is recounted on its own, right before it is read, so the two sides are read together.
+//! Views are events with nothing to recount from, so only impossible (negative) view counts are fixed.
+
+use crate::model::{Attachment, Post, Thread};
+use actix_web::web::Data;
+use anyhow::Result;
+use futures_util::StreamExt;
+use scylla::frame::value;
+use scylla::Session;
+use std::time::Duration;
+
+/// What a reconciliation found and fixed.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub threads_checked: usize,
+    pub replies_fixed: usize,
+    pub attachments_checked: usize,
+    pub references_fixed: usize,
+    pub views_fixed: usize,
+}
+
+/// Recounts one thread's replies from its positions, less deleted posts.
+async fn reconcile_thread(
+    scylla: Data<Session>,
+    thread_id: i64,
+    report: &mut Report,
+) -> Result<()> {
+    report.threads_checked += 1;
+    let post_ids = Post::fetch_thread_ids(scylla.clone(), thread_id).await?;
+    let correction = Thread::sync_reply_count(scylla, thread_id, post_ids).await?;
+    if correction != 0 {
+        report.replies_fixed += 1;
+        log::warn!(
+            "Reply count of thread {} was off by {}.",
+            thread_id,
+            -correction
+        );
+    }
+
+    Ok(())
+}
+
+/// Recounts every thread's replies, one thread at a time.
+async fn reconcile_replies(scylla: Data<Session>, report: &mut Report) -> Result<()> {
+    // Every counter, including those of threads with no posts left.
+    let mut rows = scylla
+        .query_iter("SELECT id FROM volksforo.thread_replies", ())
+        .await?
+        .into_typed::<(i64,)>();
+    while let Some(row) = rows.next().await {
+        reconcile_thread(scylla.clone(), row?.0, report).await?;
+    }
+
+    // Threads with posts but no counter were not seen above.
+    let mut rows = scylla
+        .query_iter(
+            "SELECT DISTINCT thread_id FROM volksforo.post_positions",
+            (),
+        )
+        .await?
+        .into_typed::<(i64,)>();
+    while let Some(row) = rows.next().await {
+        let thread_id = row?.0;
+        if Thread::fetch_reply_count(scylla.clone(), thread_id)
+            .await?
+            .is_none()
+        {
+            reconcile_thread(scylla.clone(), thread_id, report).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Recounts how many posts one attachment is linked to.
+async fn reconcile_attachment(
+    scylla: Data<Session>,
+    hash: &str,
+    report: &mut Report,
+) -> Result<()> {
+    report.attachments_checked += 1;
+    let correction = Attachment::sync_reference_count(scylla, hash).await?;
+    if correction != 0 {
+        report.references_fixed += 1;
+        log::warn!(
+            "Reference count of attachment {} was off by {}.",
+            hash,
+            -correction
+        );
+    }
+
+    Ok(())
+}
+
+/// Recounts how many posts each attachment is linked to, one attachment at a time.
+async fn reconcile_references(scylla: Data<Session>, report: &mut Report) -> Result<()> {
+    // Every counter, including those of files no longer attached anywhere.
+    let mut rows = scylla
+        .query_iter("SELECT hash FROM volksforo.attachment_references", ())
+        .await?
+        .into_typed::<(String,)>();
+    while let Some(row) = rows.next().await {
+        reconcile_attachment(scylla.clone(), &row?.0, report).await?;
+    }
+
+    // Files with no counter were not seen above.
+    let mut rows = scylla
+        .query_iter("SELECT hash FROM volksforo.attachments", ())
+        .await?
+        .into_typed::<(String,)>();
+    while let Some(row) = rows.next().await {
+        let hash = row?.0;
+        if Attachment::fetch_reference_count(scylla.clone(), &hash)
+            .await?
+            .is_none()
+        {
+            reconcile_attachment(scylla.clone(), &hash, report).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Raises negative view counts back to zero.
+async fn reconcile_views(scylla: Data<Session>, report: &mut Report) -> Result<()> {
+    let mut rows = scylla
+        .query_iter("SELECT id, view_count FROM volksforo.thread_views", ())
+        .await?
+        .into_typed::<(i64, value::Counter)>();
+    while let Some(row) = rows.next().await {
+        let (thread_id, count) = row?;
+        if count.0 < 0 {
+            report.views_fixed += 1;
+            log::warn!("View count of thread {} was {}.", thread_id, count.0);
+            scylla
+                .query(
+                    "UPDATE volksforo.thread_views SET view_count = view_count + ? WHERE id = ?",
+                    (value::Counter(-count.0), thread_id),
+                )
+                .await?;
+        }
+    }
+
+    let mut rows = scylla
+        .query_iter(
+            "SELECT hash, view_count FROM volksforo.attachment_views",
+            (),
+        )
+        .await?
+        .into_typed::<(String, value::Counter)>();
+    while let Some(row) = rows.next().await {
+        let (hash, count) = row?;
+        if count.0 < 0 {
+            report.views_fixed += 1;
+            log::warn!("View count of attachment {} was {}.", hash, count.0);
+            scylla
+                .query(
+                    "UPDATE volksforo.attachment_views SET view_count = view_count + ? WHERE hash = ?",
+                    (value::Counter(-count.0), &hash),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every counter once and corrects those which drifted.
+/// Replies made while a thread is recounted may be missed; the next run corrects them.
+pub async fn run(scylla: Data<Session>) -> Result<Report> {
+    let mut report = Report::default();
+
+    reconcile_replies(scylla.clone(), &mut report).await?;
+    reconcile_references(scylla.clone(), &mut report).await?;
+    reconcile_views(scylla, &mut report).await?;
+
+    log::info!(
+        "Reconciled counters: {} of {} reply counts, {} of {} attachment reference counts and {} view counts fixed.",
+        report.replies_fixed,
+        report.threads_checked,
+        report.references_fixed,
+        report.attachments_checked,
+        report.views_fixed,
+    );
+
+    Ok(report)
+}
+
+/// Runs the reconciler in the background every `period`.
+pub fn spawn(scylla: Data<Session>, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // The first tick completes immediately. Let the server start first.
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if let Err(err) = run(scylla.clone()).await {
+                log::error!("Counter reconciliation failed: {:?}", err);
+            }
+        }
+    });
+}
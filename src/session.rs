@@ -1,4 +1,4 @@
-use crate::model::{User, UserSession};
+use crate::model::{Group, User, UserSession};
 use actix_web::web::Data;
 use anyhow::Result;
 use scylla::Session as ScyllaSession;
@@ -7,20 +7,39 @@ use uuid::Uuid;
 /// Representation of the current web user, which may or may not be signed in.
 #[derive(Debug, Default)]
 pub struct Visitor {
-    pub session_id: Option<Uuid>,
+    pub session: Option<UserSession>,
     pub user: Option<User>,
 }
 
 impl Visitor {
-    pub async fn new_from_uuid(scylla: Data<ScyllaSession>, uuid: Uuid) -> Result<Self> {
+    /// Restores a visitor from their session id alongside the ids of the groups they belong to.
+    pub async fn new_from_uuid(
+        scylla: Data<ScyllaSession>,
+        uuid: Uuid,
+    ) -> Result<(Self, Vec<i32>)> {
         match UserSession::fetch(scylla.clone(), &uuid).await? {
-            Some(session) => Ok(Visitor {
-                session_id: Some(uuid),
-                user: User::fetch(scylla, session.user_id).await?,
-            }),
+            Some(session) => {
+                let (user, groups) = tokio::try_join!(
+                    User::fetch(scylla.clone(), session.user_id),
+                    Group::fetch_ids_for_user(scylla, session.user_id),
+                )?;
+
+                let groups = match user {
+                    Some(_) => groups,
+                    None => Group::guest_ids(),
+                };
+
+                Ok((
+                    Visitor {
+                        session: Some(session),
+                        user,
+                    },
+                    groups,
+                ))
+            }
             None => {
                 log::debug!("Requested session not found: {}", uuid);
-                Ok(Self::default())
+                Ok((Self::default(), Group::guest_ids()))
             }
         }
     }
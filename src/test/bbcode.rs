@@ -0,0 +1,117 @@
+use crate::bbcode::parse::{parse, Element, Node, Tag, MAX_DEPTH};
+use crate::bbcode::render;
+
+#[test]
+fn test_render_inline() {
+    assert_eq!(
+        render("[b]bold[/b] and [I]italic[/I]"),
+        "<strong>bold</strong> and <em>italic</em>"
+    );
+    assert_eq!(render("one\ntwo\r\nthree"), "one<br />two<br />three");
+}
+
+#[test]
+fn test_render_escapes_everything() {
+    assert_eq!(
+        render("<script>alert('x')</script> & \"quotes\""),
+        "&lt;script&gt;alert(&#x27;x&#x27;)&lt;/script&gt; &amp; &quot;quotes&quot;"
+    );
+    // Unknown tags are text, not markup.
+    assert_eq!(
+        render("[script]x[/script] [b onclick=x]y"),
+        "[script]x[/script] [b onclick=x]y"
+    );
+    assert_eq!(
+        render("[quote=\"<b>Chuck</b>\"]hi[/quote]"),
+        "<blockquote class=\"bbcode-quote\"><cite>&lt;b&gt;Chuck&lt;/b&gt;</cite>hi</blockquote>"
+    );
+}
+
+#[test]
+fn test_render_urls() {
+    assert_eq!(
+        render("[url]https://example.com/?a=1&b=2[/url]"),
+        "<a href=\"https://example.com/?a=1&amp;b=2\" rel=\"nofollow ugc noopener noreferrer\" target=\"_blank\">https://example.com/?a=1&amp;b=2</a>"
+    );
+    assert_eq!(
+        render("[url=http://example.com]site[/url]"),
+        "<a href=\"http://example.com\" rel=\"nofollow ugc noopener noreferrer\" target=\"_blank\">site</a>"
+    );
+    assert_eq!(
+        render("[img]https://example.com/a.png[/img]"),
+        "<img class=\"bbcode-img\" src=\"https://example.com/a.png\" alt=\"\" loading=\"lazy\" referrerpolicy=\"no-referrer\" />"
+    );
+
+    // Anything but http(s) is dropped, keeping the text.
+    assert_eq!(render("[url=javascript:alert(1)]click[/url]"), "click");
+    assert_eq!(render("[url=JaVaScRiPt:alert(1)]click[/url]"), "click");
+    assert_eq!(render("[url=//evil.example]click[/url]"), "click");
+    assert_eq!(
+        render("[url=\"http://a.com\" onmouseover=\"x\"]click[/url]"),
+        "click"
+    );
+    assert_eq!(
+        render("[img]data:image/png;base64,AAAA[/img]"),
+        "data:image/png;base64,AAAA"
+    );
+}
+
+#[test]
+fn test_render_code_is_verbatim() {
+    assert_eq!(
+        render("[code]\n[b]not bold[/b]\n<br>[/code]\nafter"),
+        "<pre class=\"bbcode-code\"><code>[b]not bold[/b]\n&lt;br&gt;</code></pre>after"
+    );
+}
+
+#[test]
+fn test_render_lists() {
+    assert_eq!(
+        render("[list]\n[*]one\n[*][b]two[/list]"),
+        "<ul><li>one</li><li><strong>two</strong></li></ul>"
+    );
+    assert_eq!(render("[list=1]stray[/list]"), "<ol><li>stray</li></ol>");
+    assert_eq!(render("[*]not a list"), "[*]not a list");
+}
+
+#[test]
+fn test_render_spoiler() {
+    assert_eq!(
+        render("[spoiler=Ending]he dies[/spoiler]"),
+        "<details class=\"bbcode-spoiler\"><summary>Ending</summary>he dies</details>"
+    );
+}
+
+#[test]
+fn test_parse_misnested() {
+    // Unclosed tags close at the end; closing an outer tag closes the inner ones.
+    assert_eq!(
+        parse("[b][i]x[/b]y[/i]"),
+        vec![
+            Node::Element(Element {
+                tag: Tag::Bold,
+                argument: None,
+                children: vec![Node::Element(Element {
+                    tag: Tag::Italic,
+                    argument: None,
+                    children: vec![Node::Text("x".to_owned())],
+                })],
+            }),
+            Node::Text("y[/i]".to_owned()),
+        ]
+    );
+    assert_eq!(
+        render("[quote]unclosed"),
+        "<blockquote class=\"bbcode-quote\">unclosed</blockquote>"
+    );
+    assert_eq!(render("[/b][b"), "[/b][b");
+}
+
+#[test]
+fn test_parse_depth_limit() {
+    let input = "[quote]".repeat(MAX_DEPTH * 4);
+    let html = render(&input);
+    assert_eq!(html.matches("<blockquote").count(), MAX_DEPTH);
+    assert_eq!(html.matches("</blockquote>").count(), MAX_DEPTH);
+    assert!(html.contains("[quote]"));
+}
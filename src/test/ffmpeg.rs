@@ -22,6 +22,17 @@ fn test_blake3() {
     );
 }
 
+#[test]
+fn test_hash_file() {
+    let hash = crate::filesystem::hash_file(Path::new("src/test/static/text.jpg")).unwrap();
+    assert_eq!(
+        hash.to_string(),
+        "8770325564caf30faf3fcfc0ff5be1a6e06afb405b8d1ce647b7dd4a694f6754".to_string()
+    );
+    assert!(crate::filesystem::is_valid_hash(&hash.to_string()));
+    assert!(!crate::filesystem::is_valid_hash("../../etc/passwd"));
+}
+
 #[test]
 fn test_ffmpeg() {
     ffmpeg::init().unwrap();
@@ -0,0 +1,149 @@
+use crate::filesystem::metadata::{read_exif_orientation, strip_jpeg, strip_png, strip_webp};
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Builds a little-endian EXIF payload with an orientation and a fake GPS entry.
+fn exif_payload(orientation: u16) -> Vec<u8> {
+    let mut payload = b"Exif\0\0II\x2A\0\x08\0\0\0".to_vec();
+    payload.extend_from_slice(&2u16.to_le_bytes());
+    // Orientation, SHORT, 1
+    payload.extend_from_slice(&0x0112u16.to_le_bytes());
+    payload.extend_from_slice(&3u16.to_le_bytes());
+    payload.extend_from_slice(&1u32.to_le_bytes());
+    payload.extend_from_slice(&orientation.to_le_bytes());
+    payload.extend_from_slice(&[0, 0]);
+    // GPSInfo pointer, LONG, 1
+    payload.extend_from_slice(&0x8825u16.to_le_bytes());
+    payload.extend_from_slice(&4u16.to_le_bytes());
+    payload.extend_from_slice(&1u32.to_le_bytes());
+    payload.extend_from_slice(&38u32.to_le_bytes());
+    payload.extend_from_slice(&0u32.to_le_bytes());
+    payload.extend_from_slice(b"GPS 51.5007N 0.1246W");
+    payload
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    // CRC is not checked by the stripper.
+    chunk.extend_from_slice(&[0, 0, 0, 0]);
+    chunk
+}
+
+fn webp_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+#[test]
+fn test_strip_jpeg() {
+    let original = std::fs::read("src/test/static/text.jpg").unwrap();
+
+    // SOI, APP0 (JFIF, 16 bytes + marker), then our EXIF, then the rest.
+    let payload = exif_payload(6);
+    let mut dirty = original[..20].to_vec();
+    dirty.extend_from_slice(&[0xFF, 0xE1]);
+    dirty.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    dirty.extend_from_slice(&payload);
+    dirty.extend_from_slice(&original[20..]);
+    dirty.extend_from_slice(b"trailing data");
+    assert_eq!(read_exif_orientation(&payload), Some(6));
+
+    let clean = strip_jpeg(&dirty).unwrap();
+    assert!(!contains(&clean, b"GPS"));
+    assert!(!contains(&clean, b"Lavc"), "COM segment survived");
+    assert!(!contains(&clean, b"trailing data"));
+    assert!(contains(&clean, b"JFIF\0"));
+    assert!(contains(&clean, b"ICC_PROFILE\0"));
+    assert!(clean.ends_with(&[0xFF, 0xD9]));
+
+    // Orientation is kept in a fresh EXIF segment.
+    let app1 = clean
+        .windows(2)
+        .position(|w| w == [0xFF, 0xE1])
+        .expect("orientation segment");
+    assert_eq!(read_exif_orientation(&clean[app1 + 4..]), Some(6));
+
+    // Stripping is stable.
+    assert_eq!(strip_jpeg(&clean).unwrap(), clean);
+    assert_eq!(infer::get(&clean).unwrap().mime_type(), "image/jpeg");
+}
+
+#[test]
+fn test_strip_png() {
+    let original = std::fs::read("src/test/static/text.png").unwrap();
+    let iend = original.len() - 12;
+
+    let mut dirty = original[..iend].to_vec();
+    dirty.extend_from_slice(&png_chunk(b"tEXt", b"Comment\0GPS 51.5007N"));
+    dirty.extend_from_slice(&png_chunk(b"eXIf", &exif_payload(1)[6..]));
+    dirty.extend_from_slice(&original[iend..]);
+    dirty.extend_from_slice(b"trailing data");
+
+    let clean = strip_png(&dirty).unwrap();
+    assert!(!contains(&clean, b"GPS"));
+    assert!(!contains(&clean, b"trailing data"));
+    assert!(contains(&clean, b"gAMA"));
+    assert!(contains(&clean, b"iCCP"));
+    assert!(clean.ends_with(&original[iend..]));
+
+    assert_eq!(strip_png(&clean).unwrap(), clean);
+    assert!(
+        strip_png(&original[..iend]).is_err(),
+        "truncated PNG accepted"
+    );
+}
+
+#[test]
+fn test_strip_webp() {
+    let original = std::fs::read("src/test/static/text.webp").unwrap();
+    let vp8 = &original[12..];
+
+    // Extended format: VP8X with EXIF and XMP flags set, 280x80 canvas.
+    const WEBP_FLAGS: u8 = 0x08 | 0x04;
+    let mut vp8x = vec![WEBP_FLAGS, 0, 0, 0];
+    vp8x.extend_from_slice(&(280u32 - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(80u32 - 1).to_le_bytes()[..3]);
+
+    let mut chunks = webp_chunk(b"VP8X", &vp8x);
+    chunks.extend_from_slice(vp8);
+    chunks.extend_from_slice(&webp_chunk(b"EXIF", &exif_payload(1)[6..]));
+    chunks.extend_from_slice(&webp_chunk(b"XMP ", b"<x:xmpmeta>GPS</x:xmpmeta>"));
+    let mut dirty = b"RIFF".to_vec();
+    dirty.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+    dirty.extend_from_slice(b"WEBP");
+    dirty.extend_from_slice(&chunks);
+
+    let clean = strip_webp(&dirty).unwrap();
+    assert!(!contains(&clean, b"GPS"));
+    assert!(!contains(&clean, b"EXIF"));
+    assert!(!contains(&clean, b"XMP "));
+    assert!(contains(&clean, vp8));
+    // Flags cleared, RIFF size matches.
+    assert_eq!(clean[20] & WEBP_FLAGS, 0);
+    assert_eq!(
+        u32::from_le_bytes(clean[4..8].try_into().unwrap()) as usize,
+        clean.len() - 8
+    );
+
+    // Simple format files pass through untouched.
+    assert_eq!(strip_webp(&original).unwrap(), original);
+}
+
+#[test]
+fn test_strip_rejects_garbage() {
+    assert!(strip_jpeg(b"GIF89a").is_err());
+    assert!(strip_jpeg(&[0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xFF]).is_err());
+    assert!(strip_png(b"GIF89a").is_err());
+    assert!(strip_webp(b"RIFF\xFF\xFF\xFF\xFFWEBP").is_err());
+}
@@ -1 +1,4 @@
+mod bbcode;
 mod ffmpeg;
+mod metadata;
+mod views;
@@ -0,0 +1,38 @@
+use crate::views::{ViewCounter, Viewer, VIEW_WINDOW};
+use std::time::Duration;
+
+#[test]
+fn test_views_deduplicate_per_visitor() {
+    let views = ViewCounter::new(VIEW_WINDOW);
+
+    assert!(views.record(Viewer::User(1), 10));
+    assert!(!views.record(Viewer::User(1), 10));
+    assert!(views.record(Viewer::User(2), 10));
+    assert!(views.record(Viewer::User(1), 11));
+    assert!(views.record(Viewer::Address("127.0.0.1".to_owned()), 10));
+    assert!(!views.record(Viewer::Address("127.0.0.1".to_owned()), 10));
+
+    assert_eq!(views.pending(), 4);
+    assert_eq!(views.pending_threads(), 2);
+}
+
+#[test]
+fn test_views_count_again_after_window() {
+    let views = ViewCounter::new(Duration::ZERO);
+
+    assert!(views.record(Viewer::User(1), 10));
+    assert!(views.record(Viewer::User(1), 10));
+    assert_eq!(views.pending(), 2);
+    assert_eq!(views.pending_threads(), 1);
+}
+
+#[test]
+fn test_views_stop_counting_new_visitors_at_limit() {
+    let views = ViewCounter::with_limit(VIEW_WINDOW, 2);
+
+    assert!(views.record(Viewer::User(1), 10));
+    assert!(views.record(Viewer::User(2), 10));
+    assert!(!views.record(Viewer::User(3), 10));
+    assert!(!views.record(Viewer::User(1), 10));
+    assert_eq!(views.pending(), 2);
+}
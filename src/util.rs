@@ -1,18 +1,26 @@
 use anyhow::Result;
 use askama_actix::Template;
 use once_cell::sync::OnceCell;
-use std::ops::Range;
+use std::ops::{Range, RangeInclusive};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::MixedScript;
 
 /// Holds the Argon2 configuration used for password checks.
 pub static ARGON2_CONFIG: OnceCell<argon2::Config> = OnceCell::new();
 
-/// Hashes user input to the Argon2 hash. Slow!
+/// Cipher of passwords hashed by [argon2_hash]. Passwords stored with any other are rehashed on login.
+pub const PASSWORD_CIPHER: &str = "argon2id";
+
+/// Length of the random salt generated for each password.
+const PASSWORD_SALT_LENGTH: usize = 16;
+
+/// Hashes user input to the Argon2 hash with a random salt. Slow!
+/// The salt and parameters are kept in the encoded hash, so changing the config does not break old hashes.
 pub fn argon2_hash(password: &str) -> Result<String> {
+    let salt: [u8; PASSWORD_SALT_LENGTH] = rand::random();
     Ok(argon2::hash_encoded(
         password.as_bytes(),
-        std::env::var("VF_SALT")
-            .expect("VF_SALT is unset")
-            .as_bytes(),
+        &salt,
         ARGON2_CONFIG.get().expect("ARGON2_CONFIG is unset"),
     )?)
 }
@@ -22,9 +30,96 @@ pub fn argon2_verify(hash: &str, password: &str) -> Result<bool> {
     Ok(argon2::verify_encoded(hash, password.as_bytes())?)
 }
 
+/// Verifies a stored password of any supported cipher.
+/// - `argon2id`: the current scheme.
+/// - `argon2`: Argon2i salted with `VF_SALT`, used before per-user salts.
+/// - `bcrypt`: XenForo's hashes, for imported accounts.
+/// - `plaintext`: seed data.
+pub fn password_verify(cipher: &str, hash: &str, password: &str) -> Result<bool> {
+    match cipher {
+        "argon2id" | "argon2" => argon2_verify(hash, password),
+        "bcrypt" => Ok(bcrypt::verify(password, hash)?),
+        // Compared as hashes so the comparison takes constant time.
+        "plaintext" => Ok(blake3::hash(hash.as_bytes()) == blake3::hash(password.as_bytes())),
+        _ => Err(anyhow::anyhow!("Unknown password cipher: {}", cipher)),
+    }
+}
+
+/// Returns true if a lightweight transaction (IF ...) was applied.
+/// The first column of an LWT result is always `[applied]`.
+pub fn is_applied(result: scylla::QueryResult) -> bool {
+    result
+        .rows
+        .unwrap_or_default()
+        .first()
+        .and_then(|row| row.columns.first())
+        .and_then(|column| column.as_ref())
+        .and_then(|value| value.as_boolean())
+        .unwrap_or(false)
+}
+
+/// Shortest and longest usernames, in characters.
+pub const USERNAME_LENGTH: RangeInclusive<usize> = 3..=32;
+
+/// Longest email address, in characters.
+pub const EMAIL_LENGTH: usize = 254;
+
 /// Normalize a username from user input.
+/// Names which look alike normalize the same (`Admin`, `admın` and `adrnin`), so only one may be registered.
+/// The result is a lookup key and is not meant to be shown.
 pub fn normalize_username(username: &str) -> String {
-    username.trim().to_lowercase()
+    let folded = username.trim().nfkc().collect::<String>().to_lowercase();
+    unicode_security::skeleton(&folded)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Normalize an email address from user input.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().nfkc().collect::<String>().to_lowercase()
+}
+
+/// Checks a new username, returning why it cannot be used.
+pub fn validate_username(username: &str) -> Result<(), &'static str> {
+    let length = username.chars().count();
+
+    if username.trim() != username {
+        Err("Usernames cannot start or end with spaces.")
+    } else if length < *USERNAME_LENGTH.start() {
+        Err("Usernames must be at least 3 characters long.")
+    } else if length > *USERNAME_LENGTH.end() {
+        Err("Usernames cannot be longer than 32 characters.")
+    } else if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.'))
+    {
+        Err("Usernames may only contain letters, numbers, spaces, and _ - .")
+    } else if username.contains("  ") {
+        Err("Usernames cannot contain consecutive spaces.")
+    } else if !username.is_single_script() {
+        Err("Usernames cannot mix letters from different alphabets.")
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks a new email address, returning why it cannot be used.
+/// Only the shape is checked. Whether it receives mail is not.
+pub fn validate_email(email: &str) -> Result<(), &'static str> {
+    match email.split_once('@') {
+        _ if email.chars().count() > EMAIL_LENGTH => Err("Email addresses cannot be that long."),
+        Some((local, domain))
+            if !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !email.chars().any(char::is_whitespace) =>
+        {
+            Ok(())
+        }
+        _ => Err("Email address is not valid."),
+    }
 }
 
 /// Snowflake ID Bucket
@@ -138,19 +233,79 @@ mod tests {
 
     #[test]
     fn test_password() {
-        std::env::set_var("VF_SALT", "Yya6#MEU6a7S3ZCPy@8yXq@h");
         ARGON2_CONFIG
-            .set(argon2::Config::default())
+            .set(argon2::Config {
+                variant: argon2::Variant::Argon2id,
+                ..Default::default()
+            })
             .expect("failed ARGON2_CONFIG");
 
         let password = "qRMFtvQ&_2Wi8bWu66aybpU!R✨";
         let hash = argon2_hash(password).expect("failed to hash");
 
-        assert_eq!(hash, "$argon2i$v=19$m=4096,t=3,p=1$WXlhNiNNRVU2YTdTM1pDUHlAOHlYcUBo$BE3zzlJr3LdhNx3xbdxOsJEaW8bgcWuFRnI029BUTZw");
-        assert!(argon2_verify(&hash, password).expect("failed to verify"));
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, argon2_hash(password).expect("failed to hash"));
+        assert!(password_verify(PASSWORD_CIPHER, &hash, password).expect("failed to verify"));
 
         let password2 = "qRMFtvQ&_2Wi8bWu66aybpU!R";
-        assert!(!argon2_verify(&hash, password2).expect("failed to verify"));
+        assert!(!password_verify(PASSWORD_CIPHER, &hash, password2).expect("failed to verify"));
+    }
+
+    #[test]
+    fn test_legacy_password() {
+        let password = "qRMFtvQ&_2Wi8bWu66aybpU!R✨";
+
+        // Argon2i with the old global salt.
+        let argon2i = "$argon2i$v=19$m=4096,t=3,p=1$WXlhNiNNRVU2YTdTM1pDUHlAOHlYcUBo$BE3zzlJr3LdhNx3xbdxOsJEaW8bgcWuFRnI029BUTZw";
+        assert!(password_verify("argon2", argon2i, password).expect("failed to verify"));
+        assert!(!password_verify("argon2", argon2i, "password").expect("failed to verify"));
+
+        // XenForo stores bcrypt with the $2y$ prefix.
+        let bcrypt = bcrypt::hash(password, 4)
+            .expect("failed to hash")
+            .replacen("$2b$", "$2y$", 1);
+        assert!(password_verify("bcrypt", &bcrypt, password).expect("failed to verify"));
+        assert!(!password_verify("bcrypt", &bcrypt, "password").expect("failed to verify"));
+
+        assert!(password_verify("plaintext", "password", "password").expect("failed to verify"));
+        assert!(!password_verify("plaintext", "password", password).expect("failed to verify"));
+
+        assert!(password_verify("md5", "", password).is_err());
+    }
+
+    #[test]
+    fn test_username() {
+        assert_eq!(normalize_username(" Admin "), normalize_username("admin"));
+        assert_eq!(normalize_username("admın"), normalize_username("admin"));
+        assert_eq!(normalize_username("adrnin"), normalize_username("admin"));
+        assert_eq!(
+            normalize_username("ＡＤＭＩＮ"),
+            normalize_username("admin")
+        );
+        assert_ne!(normalize_username("admins"), normalize_username("admin"));
+
+        assert!(validate_username("Sneed").is_ok());
+        assert!(validate_username("Chuck_Feed-1.0").is_ok());
+        assert!(validate_username("Жириновский").is_ok());
+        assert!(validate_username("ab").is_err());
+        assert!(validate_username(&"a".repeat(33)).is_err());
+        assert!(validate_username(" Sneed").is_err());
+        assert!(validate_username("Sneed  Chuck").is_err());
+        assert!(validate_username("<Sneed>").is_err());
+        // Latin 'a' with Cyrillic 'дмин'.
+        assert!(validate_username("aдмин").is_err());
+    }
+
+    #[test]
+    fn test_email() {
+        assert_eq!(normalize_email(" Sneed@Example.com "), "sneed@example.com");
+
+        assert!(validate_email("sneed@example.com").is_ok());
+        assert!(validate_email("sneed").is_err());
+        assert!(validate_email("@example.com").is_err());
+        assert!(validate_email("sneed@localhost").is_err());
+        assert!(validate_email("sneed@@example.com").is_err());
+        assert!(validate_email("sneed @example.com").is_err());
     }
 
     #[test]
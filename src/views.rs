@@ -0,0 +1,180 @@
+//! Thread view counting.
+//! Every page view used to be its own counter write, our hottest query. Views are now collected in
+//! memory, counted once per visitor and thread in a window, and written out together on an interval.
+
+use crate::model::Thread;
+use actix_web::web::Data;
+use actix_web::HttpRequest;
+use anyhow::Result;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use scylla::Session;
+use std::time::{Duration, Instant};
+
+/// How long a visitor's views of a thread count as one.
+pub const VIEW_WINDOW: Duration = Duration::from_secs(30 * 60);
+
+/// Most visitor and thread pairs remembered at once.
+/// New visitors are not counted past this until the next flush forgets old views.
+pub const SEEN_LIMIT: usize = 200_000;
+
+/// If set, guests are told apart by the address a reverse proxy forwards instead of the peer's.
+/// Without a proxy in front, that header is whatever the client wants it to be.
+static TRUSTED_PROXY: Lazy<bool> = Lazy::new(|| {
+    std::env::var("VF_TRUSTED_PROXY")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+});
+
+/// Who viewed a thread.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Viewer {
+    User(i64),
+    /// Guests are told apart by address.
+    Address(String),
+}
+
+impl Viewer {
+    pub fn from_request(req: &HttpRequest, user_id: Option<i64>) -> Self {
+        match user_id {
+            Some(id) => Self::User(id),
+            None if *TRUSTED_PROXY => Self::Address(
+                req.connection_info()
+                    .realip_remote_addr()
+                    .unwrap_or_default()
+                    .to_owned(),
+            ),
+            None => Self::Address(
+                req.peer_addr()
+                    .map(|addr| addr.ip().to_string())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+/// Collects thread views until they are flushed to Scylla.
+#[derive(Debug)]
+pub struct ViewCounter {
+    window: Duration,
+    limit: usize,
+    /// When each visitor's view of a thread was last counted.
+    seen: DashMap<(Viewer, i64), Instant>,
+    /// Views not yet written, by thread.
+    pending: DashMap<i64, i64>,
+}
+
+impl Default for ViewCounter {
+    fn default() -> Self {
+        Self::new(VIEW_WINDOW)
+    }
+}
+
+impl ViewCounter {
+    pub fn new(window: Duration) -> Self {
+        Self::with_limit(window, SEEN_LIMIT)
+    }
+
+    pub fn with_limit(window: Duration, limit: usize) -> Self {
+        Self {
+            window,
+            limit,
+            seen: Default::default(),
+            pending: Default::default(),
+        }
+    }
+
+    /// Records a view. Returns false if the visitor already viewed the thread within the window.
+    pub fn record(&self, viewer: Viewer, thread_id: i64) -> bool {
+        let now = Instant::now();
+        let mut counted = false;
+
+        // Old views are only forgotten by the flush, so a full map is never scanned here.
+        let key = (viewer, thread_id);
+        if self.seen.len() >= self.limit && !self.seen.contains_key(&key) {
+            return false;
+        }
+
+        self.seen
+            .entry(key)
+            .and_modify(|last| {
+                if now.duration_since(*last) >= self.window {
+                    *last = now;
+                    counted = true;
+                }
+            })
+            .or_insert_with(|| {
+                counted = true;
+                now
+            });
+
+        if counted {
+            *self.pending.entry(thread_id).or_insert(0) += 1;
+        }
+
+        counted
+    }
+
+    /// Returns the number of views waiting to be written.
+    pub fn pending(&self) -> i64 {
+        self.pending.iter().map(|entry| *entry.value()).sum()
+    }
+
+    /// Returns the number of threads with views waiting to be written.
+    pub fn pending_threads(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Forgets views older than the window so the visitor is counted again.
+    fn prune(&self) {
+        let now = Instant::now();
+        self.seen
+            .retain(|_, last| now.duration_since(*last) < self.window);
+    }
+
+    /// Writes every pending view to Scylla. Views which fail to write are kept for the next flush.
+    /// Returns the number of views written.
+    pub async fn flush(&self, scylla: Data<Session>) -> Result<i64> {
+        self.prune();
+
+        // Taken out first so views recorded during the flush wait for the next one.
+        let thread_ids: Vec<i64> = self.pending.iter().map(|entry| *entry.key()).collect();
+        let mut written = 0;
+        let mut error = None;
+
+        for thread_id in thread_ids {
+            let Some((_, views)) = self.pending.remove(&thread_id) else {
+                continue;
+            };
+
+            match Thread::increment_view_count(scylla.clone(), thread_id, views).await {
+                Ok(_) => written += views,
+                Err(err) => {
+                    *self.pending.entry(thread_id).or_insert(0) += views;
+                    error = Some(err);
+                }
+            }
+        }
+
+        match error {
+            Some(err) => Err(err),
+            None => Ok(written),
+        }
+    }
+}
+
+/// Flushes views in the background every `period`.
+pub fn spawn(counter: Data<ViewCounter>, scylla: Data<Session>, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+            match counter.flush(scylla.clone()).await {
+                Ok(0) => {}
+                Ok(written) => log::debug!("Flushed {} thread views.", written),
+                Err(err) => log::error!("Flushing thread views failed: {:?}", err),
+            }
+        }
+    });
+}